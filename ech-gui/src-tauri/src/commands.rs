@@ -1,11 +1,26 @@
 //! Tauri commands exposed to the frontend
 //! These are callable from JavaScript via invoke()
 
-use crate::config::{ConfigManager, Server};
-use crate::process::ProcessManager;
+use crate::config::{
+    ConfigManager, ConfigRecovery, HistorySettings, IntegritySettings, LogFileSettings, RoutingMode,
+    RoutingModeInfo, Server,
+};
+use crate::connections::Connection;
+use crate::history::{DestinationStat, HistoryRange, RouteRatio, ServerDayStat};
+use crate::integrity::IntegrityReport;
+use crate::log_files::LogFileInfo;
+use crate::logs::{LogPage, LogQuery};
+use crate::process::{ProcessManager, ServerSwitched, StopOutcome, WorkerInfo};
+use crate::process_stats::ProcessStats;
 use crate::proxy;
+use crate::server_fields::{self, FieldError, ValidatedServer};
+use crate::server_transfer::{ImportMode, ImportPreview, ServerExport};
+use crate::worker_args::{self, WorkerArgs};
+use crate::worker_binary::WorkerBinary;
 use once_cell::sync::Lazy;
-use tauri::AppHandle;
+use tauri::{AppHandle, Emitter};
+use tauri_plugin_notification::NotificationExt;
+use tauri_plugin_opener::OpenerExt;
 
 // Global managers
 static CONFIG_MANAGER: Lazy<ConfigManager> = Lazy::new(ConfigManager::new);
@@ -28,6 +43,34 @@ pub fn get_current_server_id() -> Option<String> {
     CONFIG_MANAGER.get_current_server_id()
 }
 
+/// How the config was recovered at startup, if it was unreadable
+#[tauri::command]
+pub fn get_config_recovery() -> Option<ConfigRecovery> {
+    CONFIG_MANAGER.recovery().cloned()
+}
+
+/// Tell the user the config had to be recovered. Called once during setup.
+pub fn report_config_recovery(app_handle: &AppHandle) {
+    let Some(recovery) = CONFIG_MANAGER.recovery() else {
+        return;
+    };
+    let (title, body) = if recovery.error.is_none() {
+        ("ECH Workers 配置已修正", recovery.repaired.join("\n"))
+    } else if recovery.read_only {
+        ("ECH Workers 无法读取配置", recovery.error.clone().unwrap_or_default())
+    } else if recovery.restored_from.is_some() {
+        ("ECH Workers 配置已恢复", "配置文件已损坏，已从最近的备份恢复".to_string())
+    } else {
+        ("ECH Workers 配置已恢复", "配置文件已损坏且没有可用的备份，已重置为默认配置".to_string())
+    };
+    let _ = app_handle
+        .notification()
+        .builder()
+        .title(title)
+        .body(body)
+        .show();
+}
+
 #[tauri::command]
 pub fn set_current_server(id: String) -> Result<(), String> {
     CONFIG_MANAGER.set_current_server(&id);
@@ -50,8 +93,24 @@ pub fn add_server(name: String) -> Result<Server, String> {
         .ok_or_else(|| "添加服务器失败".to_string())
 }
 
+/// Build a new server from a pasted `ech-workers ...` command line. The
+/// server is returned for review and not saved.
+#[tauri::command]
+pub fn parse_worker_command(command_line: String) -> Result<Server, String> {
+    let args = WorkerArgs::parse(&command_line)?;
+    Ok(Server {
+        name: "导入的服务器".to_string(),
+        ..args.to_server()
+    })
+}
+
 #[tauri::command]
 pub fn update_server(server: Server) -> Result<(), String> {
+    worker_args::validate_extras(&server)?;
+    if PROCESS_MANAGER.is_running(&server.id) {
+        return Err("服务器正在运行，请先停止再修改配置".to_string());
+    }
+    PROCESS_MANAGER.cancel_restart(&server.id);
     if CONFIG_MANAGER.update_server(server) {
         CONFIG_MANAGER.save()
     } else {
@@ -59,12 +118,85 @@ pub fn update_server(server: Server) -> Result<(), String> {
     }
 }
 
+/// Export the selected servers to a file in the downloads folder and
+/// reveal it. Returns the file's path.
+#[tauri::command]
+pub fn export_servers(app_handle: AppHandle, ids: Vec<String>, include_token: bool) -> Result<String, String> {
+    let servers: Vec<Server> = CONFIG_MANAGER
+        .get_servers()
+        .into_iter()
+        .filter(|s| ids.contains(&s.id))
+        .collect();
+    if servers.is_empty() {
+        return Err("请选择要导出的服务器".to_string());
+    }
+    
+    let path = ServerExport::new(servers, include_token).save()?;
+    let _ = app_handle.opener().reveal_item_in_dir(&path);
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Show what importing an export document would do, without changing anything
+#[tauri::command]
+pub fn preview_import_servers(
+    content: String,
+    mode: ImportMode,
+    keep_extras: bool,
+) -> Result<ImportPreview, String> {
+    ImportPreview::new(ServerExport::parse(&content)?, &CONFIG_MANAGER.get_servers(), mode, keep_extras)
+}
+
+/// Import the servers of an export document, skipping duplicates. Extra
+/// args and environment variables are only kept with `keep_extras`.
+/// Returns the number of servers imported.
+#[tauri::command]
+pub fn import_servers(content: String, mode: ImportMode, keep_extras: bool) -> Result<usize, String> {
+    let preview = ImportPreview::new(
+        ServerExport::parse(&content)?,
+        &CONFIG_MANAGER.get_servers(),
+        mode,
+        keep_extras,
+    )?;
+    let servers = preview.into_servers();
+    
+    match mode {
+        ImportMode::Merge => CONFIG_MANAGER.add_servers(servers.clone()),
+        ImportMode::Replace => {
+            if servers.is_empty() {
+                return Err("导入文件中没有可用的服务器".to_string());
+            }
+            if !PROCESS_MANAGER.workers().is_empty() {
+                return Err("替换服务器前请先停止所有运行中的服务器".to_string());
+            }
+            CONFIG_MANAGER.replace_servers(servers.clone());
+        }
+    }
+    CONFIG_MANAGER.save()?;
+    Ok(servers.len())
+}
+
+/// Routing modes with their labels, in display order
+#[tauri::command]
+pub fn get_routing_modes() -> Vec<RoutingModeInfo> {
+    RoutingMode::ALL.into_iter().map(RoutingMode::info).collect()
+}
+
+/// Check the fields of a server, returning an error per invalid field
+#[tauri::command]
+pub fn validate_server(server: serde_json::Value) -> Result<Vec<FieldError>, String> {
+    server_fields::validate_input(server)
+}
+
 #[tauri::command]
 pub fn delete_server(id: String) -> Result<(), String> {
     // Don't allow deleting if only one server
     if CONFIG_MANAGER.get_servers().len() <= 1 {
         return Err("至少需要保留一个服务器配置".to_string());
     }
+    if PROCESS_MANAGER.is_running(&id) {
+        return Err("服务器正在运行，请先停止再删除".to_string());
+    }
+    PROCESS_MANAGER.cancel_restart(&id);
     
     if CONFIG_MANAGER.delete_server(&id) {
         CONFIG_MANAGER.save()
@@ -84,33 +216,283 @@ pub fn rename_server(id: String, new_name: String) -> Result<(), String> {
 
 // ============ Process Commands ============
 
+/// Look up a server by id, defaulting to the current one
+fn resolve_server(id: Option<String>) -> Result<Server, String> {
+    match id {
+        Some(id) => CONFIG_MANAGER
+            .get_servers()
+            .into_iter()
+            .find(|s| s.id == id)
+            .ok_or_else(|| "服务器不存在".to_string()),
+        None => CONFIG_MANAGER
+            .get_current_server()
+            .ok_or_else(|| "没有选择服务器".to_string()),
+    }
+}
+
+/// Check a server can be started and apply the current settings to the
+/// process manager. Returns the server with its fields normalized.
+fn prepare_start(server: &Server) -> Result<Server, String> {
+    let validated = ValidatedServer::validate(server).map_err(|errors| {
+        errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("；")
+    })?;
+    
+    PROCESS_MANAGER
+        .log_files()
+        .configure(CONFIG_MANAGER.get_log_file_settings());
+    PROCESS_MANAGER
+        .history()
+        .configure(CONFIG_MANAGER.get_history_settings());
+    configure_locator();
+    Ok(validated.apply(server))
+}
+
+/// Start a server's worker and wait until it is ready. Runs off the main
+/// thread since readiness can take several seconds.
 #[tauri::command]
-pub fn start_process(app_handle: AppHandle) -> Result<String, String> {
-    let server = CONFIG_MANAGER
-        .get_current_server()
-        .ok_or_else(|| "没有选择服务器".to_string())?;
+pub async fn start_process(app_handle: AppHandle, id: Option<String>) -> Result<String, String> {
+    let server = prepare_start(&resolve_server(id)?)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        PROCESS_MANAGER.start(&server, app_handle)?;
+        Ok(format!("已启动服务器: {}", server.name))
+    })
+    .await
+    .map_err(|e| format!("启动任务失败: {}", e))?
+}
+
+/// Switch from the current server to another one without leaving its
+/// listen port unserved, then make it the current server. An enabled
+/// system proxy follows the new listen address.
+#[tauri::command]
+pub async fn switch_server(app_handle: AppHandle, id: String) -> Result<String, String> {
+    let old = resolve_server(None)?;
+    // Normalized like at its start, as a failed handoff restarts it
+    let old = if PROCESS_MANAGER.is_running(&old.id) {
+        prepare_start(&old)?
+    } else {
+        old
+    };
+    let new = prepare_start(&resolve_server(Some(id))?)?;
     
-    if server.server.is_empty() {
-        return Err("请输入服务地址".to_string());
-    }
-    if server.listen.is_empty() {
-        return Err("请输入监听地址".to_string());
-    }
+    let proxy_enabled = proxy::get_proxy_status();
+    let mut proxy_error = None;
+    let result = PROCESS_MANAGER
+        .switch(&old, &new, &app_handle, || {
+            // Repoint the proxy before the old worker goes away
+            if proxy_enabled && old.listen != new.listen {
+                proxy_error = proxy::set_system_proxy(true, &new.listen).err();
+            }
+        })
+        .await;
     
-    PROCESS_MANAGER.start(&server, app_handle)?;
+    let mode = match result {
+        Ok(mode) => mode,
+        Err(e) => {
+            // Don't leave the system proxy pointing at a port nobody serves
+            if proxy_enabled && !PROCESS_MANAGER.is_running(&old.id) {
+                let _ = proxy::set_system_proxy(false, &old.listen);
+            }
+            return Err(e);
+        }
+    };
+    
+    CONFIG_MANAGER.set_current_server(&new.id);
+    CONFIG_MANAGER.save()?;
     
-    Ok(format!("已启动服务器: {}", server.name))
+    let _ = app_handle.emit(
+        "server-switched",
+        ServerSwitched {
+            from: old.id.clone(),
+            to: new.id.clone(),
+            mode,
+            proxy_enabled: proxy_enabled && proxy_error.is_none(),
+        },
+    );
+    match proxy_error {
+        Some(e) => Err(format!("已切换到服务器 {}，但更新系统代理失败: {}", new.name, e)),
+        None => Ok(format!("已切换到服务器: {}", new.name)),
+    }
+}
+
+#[tauri::command]
+pub async fn stop_process(app_handle: AppHandle, id: Option<String>) -> Result<String, String> {
+    let server = resolve_server(id)?;
+    let message = match PROCESS_MANAGER.stop(&server.id, &app_handle).await? {
+        StopOutcome::Graceful => "进程已停止",
+        StopOutcome::Forced => "进程未在规定时间内退出，已强制停止",
+        StopOutcome::NotRunning => "进程未运行",
+    };
+    Ok(message.to_string())
+}
+
+/// Kill workers left running by a crashed previous instance. Called once
+/// during setup, before any worker starts.
+pub fn cleanup_leftover_workers(app_handle: &AppHandle) {
+    PROCESS_MANAGER.cleanup_leftovers(app_handle);
+}
+
+#[tauri::command]
+pub async fn stop_all_processes(app_handle: AppHandle) -> Result<String, String> {
+    PROCESS_MANAGER.stop_all(&app_handle).await?;
+    Ok("所有进程已停止".to_string())
+}
+
+#[tauri::command]
+pub fn is_process_running(id: Option<String>) -> bool {
+    resolve_server(id).is_ok_and(|s| PROCESS_MANAGER.is_running(&s.id))
+}
+
+#[tauri::command]
+pub fn get_running_workers() -> Vec<WorkerInfo> {
+    PROCESS_MANAGER.workers()
+}
+
+#[tauri::command]
+pub fn get_process_stats() -> Vec<ProcessStats> {
+    PROCESS_MANAGER.stats()
+}
+
+#[tauri::command]
+pub fn get_logs(query: LogQuery) -> LogPage {
+    PROCESS_MANAGER.logs().query(&query)
+}
+
+#[tauri::command]
+pub fn get_connections() -> Vec<Connection> {
+    PROCESS_MANAGER.connections().list()
+}
+
+// ============ Worker Executable Commands ============
+
+#[tauri::command]
+pub fn get_worker_path() -> Option<String> {
+    CONFIG_MANAGER.get_worker_path()
+}
+
+#[tauri::command]
+pub fn set_worker_path(path: Option<String>) -> Result<(), String> {
+    CONFIG_MANAGER.set_worker_path(path);
+    CONFIG_MANAGER.save()?;
+    PROCESS_MANAGER
+        .locator()
+        .configure(CONFIG_MANAGER.get_worker_path());
+    Ok(())
+}
+
+/// Resolve and probe the ech-workers executable that the next start uses
+#[tauri::command]
+pub async fn get_worker_binary() -> Result<WorkerBinary, String> {
+    configure_locator();
+    tauri::async_runtime::spawn_blocking(|| PROCESS_MANAGER.locator().resolve())
+        .await
+        .map_err(|e| format!("检测 ech-workers 失败: {}", e))?
+}
+
+#[tauri::command]
+pub fn get_integrity_settings() -> IntegritySettings {
+    CONFIG_MANAGER.get_integrity_settings()
+}
+
+#[tauri::command]
+pub fn set_integrity_settings(settings: IntegritySettings) -> Result<(), String> {
+    CONFIG_MANAGER.set_integrity_settings(settings.clone());
+    CONFIG_MANAGER.save()?;
+    PROCESS_MANAGER.locator().configure_integrity(settings);
+    Ok(())
+}
+
+/// Hash and verification state of the ech-workers executable, without
+/// running it
+#[tauri::command]
+pub async fn get_worker_integrity() -> Result<IntegrityReport, String> {
+    configure_locator();
+    tauri::async_runtime::spawn_blocking(|| PROCESS_MANAGER.locator().inspect())
+        .await
+        .map_err(|e| format!("校验 ech-workers 失败: {}", e))?
+}
+
+fn configure_locator() {
+    PROCESS_MANAGER
+        .locator()
+        .configure(CONFIG_MANAGER.get_worker_path());
+    PROCESS_MANAGER
+        .locator()
+        .configure_integrity(CONFIG_MANAGER.get_integrity_settings());
+}
+
+// ============ Log File Commands ============
+
+#[tauri::command]
+pub fn get_log_file_settings() -> LogFileSettings {
+    CONFIG_MANAGER.get_log_file_settings()
+}
+
+#[tauri::command]
+pub fn set_log_file_settings(settings: LogFileSettings) -> Result<(), String> {
+    CONFIG_MANAGER.set_log_file_settings(settings.clone());
+    CONFIG_MANAGER.save()?;
+    PROCESS_MANAGER.log_files().configure(settings);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_log_files() -> Vec<LogFileInfo> {
+    PROCESS_MANAGER.log_files().list()
+}
+
+#[tauri::command]
+pub fn open_log_file(app_handle: AppHandle, name: String) -> Result<(), String> {
+    let path = PROCESS_MANAGER.log_files().resolve(&name)?;
+    app_handle
+        .opener()
+        .open_path(path.to_string_lossy(), None::<&str>)
+        .map_err(|e| format!("打开日志文件失败: {}", e))
+}
+
+#[tauri::command]
+pub fn delete_log_file(name: String) -> Result<(), String> {
+    PROCESS_MANAGER.log_files().delete(&name)
+}
+
+// ============ History Commands ============
+
+#[tauri::command]
+pub fn get_history_settings() -> HistorySettings {
+    CONFIG_MANAGER.get_history_settings()
+}
+
+#[tauri::command]
+pub fn set_history_settings(settings: HistorySettings) -> Result<(), String> {
+    CONFIG_MANAGER.set_history_settings(settings.clone());
+    CONFIG_MANAGER.save()?;
+    PROCESS_MANAGER.history().configure(settings);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_top_destinations(range: HistoryRange, limit: Option<usize>) -> Result<Vec<DestinationStat>, String> {
+    PROCESS_MANAGER
+        .history()
+        .top_destinations(range, limit.unwrap_or(20))
+}
+
+#[tauri::command]
+pub fn get_route_ratio(range: HistoryRange) -> Result<RouteRatio, String> {
+    PROCESS_MANAGER.history().route_ratio(range)
 }
 
 #[tauri::command]
-pub fn stop_process(app_handle: AppHandle) -> Result<String, String> {
-    PROCESS_MANAGER.stop(&app_handle)?;
-    Ok("进程已停止".to_string())
+pub fn get_server_daily_sessions(range: HistoryRange) -> Result<Vec<ServerDayStat>, String> {
+    PROCESS_MANAGER.history().sessions_per_server_per_day(range)
 }
 
 #[tauri::command]
-pub fn is_process_running() -> bool {
-    PROCESS_MANAGER.is_running()
+pub fn clear_history() -> Result<(), String> {
+    PROCESS_MANAGER.history().clear()
 }
 
 // ============ Proxy Commands ============
@@ -1,13 +1,21 @@
 //! Configuration management for ECH Workers GUI
 //! Handles server configs, persistence, and cross-platform config paths
 
-use dirs;
-use parking_lot::RwLock;
+use chrono::Local;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::PathBuf;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+use crate::config_migrations::{self, MigrationError, CURRENT_SCHEMA_VERSION};
+use crate::integrity;
+use crate::worker_args::{DEFAULT_DNS, DEFAULT_ECH, DEFAULT_LISTEN};
+
 /// Single server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Server {
@@ -26,11 +34,120 @@ pub struct Server {
     #[serde(default)]
     pub ech: String,
     #[serde(default = "default_routing_mode")]
-    pub routing_mode: String,
+    pub routing_mode: RoutingMode,
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    /// How long a start waits for the worker to start listening
+    #[serde(default = "default_ready_timeout_secs")]
+    pub ready_timeout_secs: u64,
+    /// How long a stop waits for the worker to exit before killing it
+    #[serde(default = "default_stop_grace_ms")]
+    pub stop_grace_ms: u64,
+    /// Additional worker flags by name without the dash, for flags the
+    /// GUI doesn't manage itself
+    #[serde(default)]
+    pub extra_args: BTreeMap<String, String>,
+    /// Additional environment variables for the worker
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+fn default_routing_mode() -> RoutingMode {
+    RoutingMode::BypassCn
+}
+
+fn default_ready_timeout_secs() -> u64 {
+    30
+}
+
+fn default_stop_grace_ms() -> u64 {
+    3000
+}
+
+/// Which traffic the worker sends through the tunnel, as `-routing`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingMode {
+    /// Everything goes through the proxy
+    Global,
+    /// Mainland China addresses connect directly
+    BypassCn,
+    /// The system proxy is left alone
+    None,
+}
+
+/// A routing mode as presented in the UI
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutingModeInfo {
+    pub mode: RoutingMode,
+    pub label: &'static str,
+    pub description: &'static str,
+}
+
+impl RoutingMode {
+    pub const ALL: [RoutingMode; 3] = [RoutingMode::Global, RoutingMode::BypassCn, RoutingMode::None];
+    
+    /// Value of the worker's `-routing` flag
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RoutingMode::Global => "global",
+            RoutingMode::BypassCn => "bypass_cn",
+            RoutingMode::None => "none",
+        }
+    }
+    
+    pub fn info(self) -> RoutingModeInfo {
+        let (label, description) = match self {
+            RoutingMode::Global => ("全局代理", "所有流量通过代理"),
+            RoutingMode::BypassCn => ("🇨🇳 跳过大陆", "中国大陆直连"),
+            RoutingMode::None => ("不改变", "仅本地代理"),
+        };
+        RoutingModeInfo {
+            mode: self,
+            label,
+            description,
+        }
+    }
+}
+
+impl std::str::FromStr for RoutingMode {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.as_str() == s)
+            .ok_or_else(|| format!("未知的分流模式 {}，可选 global、bypass_cn、none", s))
+    }
 }
 
-fn default_routing_mode() -> String {
-    "bypass_cn".to_string()
+/// Automatic restart policy applied when the worker exits unexpectedly
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestartPolicy {
+    pub enabled: bool,
+    /// Delay before the first restart, doubled on each further attempt
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Restarts allowed within `window_secs` before giving up
+    pub max_retries: u32,
+    pub window_secs: u64,
+    /// Also restart a worker that exits or fails before it first listens,
+    /// e.g. because fetching the ECH config failed
+    pub retry_startup_failures: bool,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30_000,
+            max_retries: 5,
+            window_secs: 300,
+            retry_startup_failures: true,
+        }
+    }
 }
 
 impl Default for Server {
@@ -39,12 +156,93 @@ impl Default for Server {
             id: Uuid::new_v4().to_string(),
             name: "默认服务器".to_string(),
             server: "example.com:443".to_string(),
-            listen: "127.0.0.1:30000".to_string(),
+            listen: DEFAULT_LISTEN.to_string(),
             token: String::new(),
             ip: "saas.sin.fan".to_string(),
-            dns: "dns.alidns.com/dns-query".to_string(),
-            ech: "cloudflare-ech.com".to_string(),
-            routing_mode: "bypass_cn".to_string(),
+            dns: DEFAULT_DNS.to_string(),
+            ech: DEFAULT_ECH.to_string(),
+            routing_mode: RoutingMode::BypassCn,
+            restart_policy: RestartPolicy::default(),
+            ready_timeout_secs: default_ready_timeout_secs(),
+            stop_grace_ms: default_stop_grace_ms(),
+            extra_args: BTreeMap::new(),
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+/// On-disk logging of worker output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogFileSettings {
+    pub enabled: bool,
+    /// Start a new file once the current one reaches this size
+    pub max_file_size_kb: u64,
+    /// Start a new file once the current one has been open this long
+    pub max_file_age_hours: u64,
+    /// Number of files kept before the oldest are deleted
+    pub max_files: usize,
+}
+
+impl Default for LogFileSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_file_size_kb: 5120,
+            max_file_age_hours: 24,
+            max_files: 10,
+        }
+    }
+}
+
+/// Local connection history database
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistorySettings {
+    pub enabled: bool,
+    /// Tunnels older than this are deleted
+    pub retention_days: u64,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: 30,
+        }
+    }
+}
+
+/// What to do when the worker executable doesn't match a trusted hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityMode {
+    /// Don't check the executable
+    Off,
+    /// Log a warning and start anyway
+    Warn,
+    /// Refuse to start
+    Enforce,
+}
+
+/// Verification of the ech-workers executable before it is run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IntegritySettings {
+    pub mode: IntegrityMode,
+    /// SHA-256 hashes trusted in addition to the bundled list, in hex
+    pub trusted_hashes: Vec<String>,
+}
+
+impl Default for IntegritySettings {
+    fn default() -> Self {
+        Self {
+            mode: if integrity::has_bundled_hashes() {
+                IntegrityMode::Warn
+            } else {
+                IntegrityMode::Off
+            },
+            trusted_hashes: Vec::new(),
         }
     }
 }
@@ -52,43 +250,166 @@ impl Default for Server {
 /// Application configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
+    /// Format version of the file, see `config_migrations`
+    #[serde(default)]
+    pub schema_version: u32,
     pub servers: Vec<Server>,
     pub current_server_id: Option<String>,
+    #[serde(default)]
+    pub log_files: LogFileSettings,
+    #[serde(default)]
+    pub history: HistorySettings,
+    /// Explicit ech-workers executable; searched for when unset
+    #[serde(default)]
+    pub worker_path: Option<String>,
+    #[serde(default)]
+    pub integrity: IntegritySettings,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         let default_server = Server::default();
         Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
             current_server_id: Some(default_server.id.clone()),
             servers: vec![default_server],
+            log_files: LogFileSettings::default(),
+            history: HistorySettings::default(),
+            worker_path: None,
+            integrity: IntegritySettings::default(),
         }
     }
 }
 
+/// Number of config backups kept
+const MAX_BACKUPS: usize = 10;
+
+/// Minimum time between two backups, so frequent small saves don't rotate
+/// the older backups away
+const BACKUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Why the config file could not be loaded
+#[derive(Debug, Clone)]
+enum LoadError {
+    /// The file is unreadable or malformed
+    Corrupt(String),
+    /// The file was written by a newer version of the app
+    NewerVersion(String),
+}
+
+impl From<MigrationError> for LoadError {
+    fn from(error: MigrationError) -> Self {
+        match error {
+            MigrationError::NewerVersion(_) => LoadError::NewerVersion(error.to_string()),
+            MigrationError::Invalid(message) => LoadError::Corrupt(message),
+        }
+    }
+}
+
+/// What happened when the config file could not be read as is at startup
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigRecovery {
+    /// Why the config file was rejected, or `None` if it only needed repairs
+    pub error: Option<String>,
+    /// The file was written by a newer version of the app. It is left
+    /// untouched and changes are not saved.
+    pub read_only: bool,
+    /// Where the unreadable file was moved
+    pub quarantined: Option<String>,
+    /// Backup the config was restored from, or `None` if it was reset
+    pub restored_from: Option<String>,
+    /// Invalid values that were replaced while loading the file
+    pub repaired: Vec<String>,
+}
+
 /// Configuration manager with thread-safe access
 pub struct ConfigManager {
     config: RwLock<AppConfig>,
     config_path: PathBuf,
+    backup_dir: PathBuf,
+    last_backup: Mutex<Option<Instant>>,
+    recovery: Option<ConfigRecovery>,
 }
 
 impl ConfigManager {
-    /// Create a new ConfigManager and load existing config
+    /// Create a new ConfigManager and load existing config. An unreadable
+    /// config file is quarantined and replaced by the newest valid backup;
+    /// one from a newer version is left alone and the defaults are used
+    /// without saving.
     pub fn new() -> Self {
-        let config_dir = Self::get_config_dir();
+        Self::load(Self::get_config_dir())
+    }
+    
+    /// Create a ConfigManager for the config in `config_dir`
+    fn load(config_dir: PathBuf) -> Self {
         fs::create_dir_all(&config_dir).ok();
         
         let config_path = config_dir.join("config.json");
-        let config = Self::load_from_path(&config_path).unwrap_or_default();
+        let backup_dir = config_dir.join("backups");
         
-        Self {
-            config: RwLock::new(config),
+        let mut manager = Self {
+            config: RwLock::new(AppConfig::default()),
             config_path,
+            backup_dir,
+            last_backup: Mutex::new(None),
+            recovery: None,
+        };
+        
+        match Self::load_from_path(&manager.config_path) {
+            Ok(Some((config, version, repaired))) => {
+                *manager.config.write() = config;
+                // Write the upgraded file, backing up the original first
+                if version < CURRENT_SCHEMA_VERSION || !repaired.is_empty() {
+                    let _ = manager.save();
+                }
+                if !repaired.is_empty() {
+                    manager.recovery = Some(ConfigRecovery {
+                        error: None,
+                        read_only: false,
+                        quarantined: None,
+                        restored_from: None,
+                        repaired,
+                    });
+                }
+            }
+            Ok(None) => {}
+            Err(LoadError::NewerVersion(error)) => {
+                manager.recovery = Some(ConfigRecovery {
+                    error: Some(error),
+                    read_only: true,
+                    quarantined: None,
+                    restored_from: None,
+                    repaired: Vec::new(),
+                });
+            }
+            Err(LoadError::Corrupt(error)) => {
+                let quarantined = manager.quarantine();
+                let restored = manager.newest_valid_backup();
+                let restored_from = restored.map(|(path, config)| {
+                    *manager.config.write() = config;
+                    path.to_string_lossy().to_string()
+                });
+                // Put a readable file back, keeping the quarantined original
+                let _ = manager.write_atomic();
+                manager.recovery = Some(ConfigRecovery {
+                    error: Some(error),
+                    read_only: false,
+                    quarantined: quarantined.map(|p| p.to_string_lossy().to_string()),
+                    restored_from,
+                    repaired: Vec::new(),
+                });
+            }
         }
+        manager
+    }
+    
+    /// How the config was recovered at startup, if it had to be
+    pub fn recovery(&self) -> Option<&ConfigRecovery> {
+        self.recovery.as_ref()
     }
     
     /// Get platform-specific config directory
-    fn get_config_dir() -> PathBuf {
+    pub fn get_config_dir() -> PathBuf {
         if cfg!(target_os = "windows") {
             dirs::config_dir()
                 .unwrap_or_else(|| PathBuf::from("."))
@@ -106,24 +427,113 @@ impl ConfigManager {
         }
     }
     
-    /// Load config from file path
-    fn load_from_path(path: &PathBuf) -> Option<AppConfig> {
-        if path.exists() {
-            let content = fs::read_to_string(path).ok()?;
-            serde_json::from_str(&content).ok()
-        } else {
-            None
+    /// Load config from file path, upgraded to the current schema, along
+    /// with the version it was written with and the values that had to be
+    /// repaired; `Ok(None)` if there is no file yet
+    fn load_from_path(path: &Path) -> Result<Option<(AppConfig, u32, Vec<String>)>, LoadError> {
+        if !path.exists() {
+            return Ok(None);
         }
+        let corrupt = |e: serde_json::Error| LoadError::Corrupt(format!("配置文件已损坏: {}", e));
+        let content = fs::read_to_string(path)
+            .map_err(|e| LoadError::Corrupt(format!("读取配置失败: {}", e)))?;
+        let mut value = serde_json::from_str(&content).map_err(corrupt)?;
+        let version = config_migrations::migrate(&mut value)?;
+        let repaired = repair_routing_modes(&mut value);
+        let config = serde_json::from_value(value).map_err(corrupt)?;
+        Ok(Some((config, version, repaired)))
     }
     
-    /// Save current config to file
+    /// Save current config to file, backing up the previous one. Refused
+    /// while the file on disk is from a newer version.
     pub fn save(&self) -> Result<(), String> {
-        let config = self.config.read();
-        let json = serde_json::to_string_pretty(&*config)
+        if self.recovery.as_ref().is_some_and(|r| r.read_only) {
+            return Err("配置文件来自更新版本的程序，修改无法保存".to_string());
+        }
+        self.backup();
+        self.write_atomic()
+    }
+    
+    /// Write the config to a temporary file and move it into place, so a
+    /// crash never leaves a truncated file behind
+    fn write_atomic(&self) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(&*self.config.read())
             .map_err(|e| format!("序列化配置失败: {}", e))?;
-        fs::write(&self.config_path, json)
-            .map_err(|e| format!("保存配置失败: {}", e))?;
-        Ok(())
+        
+        let tmp_path = self.config_path.with_extension("json.tmp");
+        let write = || -> std::io::Result<()> {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(json.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, &self.config_path)
+        };
+        write().map_err(|e| {
+            let _ = fs::remove_file(&tmp_path);
+            format!("保存配置失败: {}", e)
+        })
+    }
+    
+    /// Copy the current config file into the backup directory, at most
+    /// once per `BACKUP_INTERVAL`, keeping the newest `MAX_BACKUPS`
+    fn backup(&self) {
+        let mut last_backup = self.last_backup.lock();
+        if last_backup.is_some_and(|t| t.elapsed() < BACKUP_INTERVAL) {
+            return;
+        }
+        // Only back up a file that is worth restoring
+        if !matches!(Self::load_from_path(&self.config_path), Ok(Some(_))) {
+            return;
+        }
+        if fs::create_dir_all(&self.backup_dir).is_err() {
+            return;
+        }
+        
+        let name = format!("config-{}.json", Local::now().format("%Y%m%d-%H%M%S"));
+        if fs::copy(&self.config_path, self.backup_dir.join(name)).is_err() {
+            return;
+        }
+        *last_backup = Some(Instant::now());
+        
+        for old in self.backups().into_iter().skip(MAX_BACKUPS) {
+            let _ = fs::remove_file(old);
+        }
+    }
+    
+    /// Backup files, newest first
+    fn backups(&self) -> Vec<PathBuf> {
+        let Ok(dir) = fs::read_dir(&self.backup_dir) else {
+            return Vec::new();
+        };
+        let mut paths: Vec<PathBuf> = dir
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .map(|n| n.to_string_lossy())
+                    .is_some_and(|n| n.starts_with("config-") && n.ends_with(".json"))
+            })
+            .collect();
+        // Timestamped names sort chronologically
+        paths.sort_by(|a, b| b.cmp(a));
+        paths
+    }
+    
+    /// The newest backup that still parses
+    fn newest_valid_backup(&self) -> Option<(PathBuf, AppConfig)> {
+        self.backups()
+            .into_iter()
+            .find_map(|path| {
+                let (config, _, _) = Self::load_from_path(&path).ok()??;
+                Some((path, config))
+            })
+    }
+    
+    /// Move an unreadable config file aside for inspection
+    fn quarantine(&self) -> Option<PathBuf> {
+        let name = format!("config.corrupt-{}.json", Local::now().format("%Y%m%d-%H%M%S"));
+        let target = self.config_path.with_file_name(name);
+        fs::rename(&self.config_path, &target).ok()?;
+        Some(target)
     }
     
     /// Get all servers
@@ -166,6 +576,21 @@ impl ConfigManager {
         id
     }
     
+    /// Add imported servers after the existing ones
+    pub fn add_servers(&self, servers: Vec<Server>) {
+        self.config.write().servers.extend(servers);
+    }
+    
+    /// Replace all servers, keeping the current server if it is still there
+    pub fn replace_servers(&self, servers: Vec<Server>) {
+        let mut config = self.config.write();
+        config.servers = servers;
+        let current = config.current_server_id.clone();
+        if !config.servers.iter().any(|s| Some(&s.id) == current.as_ref()) {
+            config.current_server_id = config.servers.first().map(|s| s.id.clone());
+        }
+    }
+    
     /// Update existing server
     pub fn update_server(&self, server: Server) -> bool {
         let mut config = self.config.write();
@@ -196,6 +621,46 @@ impl ConfigManager {
         config.servers.len() < initial_len
     }
     
+    /// Get log file settings
+    pub fn get_log_file_settings(&self) -> LogFileSettings {
+        self.config.read().log_files.clone()
+    }
+    
+    /// Set log file settings
+    pub fn set_log_file_settings(&self, settings: LogFileSettings) {
+        self.config.write().log_files = settings;
+    }
+    
+    /// Get connection history settings
+    pub fn get_history_settings(&self) -> HistorySettings {
+        self.config.read().history.clone()
+    }
+    
+    /// Set connection history settings
+    pub fn set_history_settings(&self, settings: HistorySettings) {
+        self.config.write().history = settings;
+    }
+    
+    /// Get the explicit ech-workers executable path
+    pub fn get_worker_path(&self) -> Option<String> {
+        self.config.read().worker_path.clone()
+    }
+    
+    /// Set the explicit ech-workers executable path
+    pub fn set_worker_path(&self, path: Option<String>) {
+        self.config.write().worker_path = path.filter(|p| !p.trim().is_empty());
+    }
+    
+    /// Get executable integrity settings
+    pub fn get_integrity_settings(&self) -> IntegritySettings {
+        self.config.read().integrity.clone()
+    }
+    
+    /// Set executable integrity settings
+    pub fn set_integrity_settings(&self, settings: IntegritySettings) {
+        self.config.write().integrity = settings;
+    }
+    
     /// Rename server
     pub fn rename_server(&self, id: &str, new_name: &str) -> bool {
         let mut config = self.config.write();
@@ -207,3 +672,190 @@ impl ConfigManager {
         }
     }
 }
+
+/// Replace routing modes this build doesn't know with `global`, as the
+/// schema 2 migration does, so one hand-edited server doesn't reject the
+/// whole file. Returns a notice per replaced value.
+fn repair_routing_modes(config: &mut Value) -> Vec<String> {
+    let Some(servers) = config.get_mut("servers").and_then(Value::as_array_mut) else {
+        return Vec::new();
+    };
+    let mut repaired = Vec::new();
+    for server in servers.iter_mut().filter_map(Value::as_object_mut) {
+        let error = match server.get("routing_mode") {
+            None => continue,
+            Some(Value::String(mode)) => match mode.parse::<RoutingMode>() {
+                Ok(_) => continue,
+                Err(e) => e,
+            },
+            Some(other) => format!("无效的分流模式 {}", other),
+        };
+        let name = server.get("name").and_then(Value::as_str).unwrap_or_default();
+        repaired.push(format!(
+            "服务器 {}: {}，已改用 {}",
+            name,
+            error,
+            RoutingMode::Global.as_str()
+        ));
+        server.insert("routing_mode".to_string(), Value::from(RoutingMode::Global.as_str()));
+    }
+    repaired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn write_config(path: &Path, config: &AppConfig) {
+        fs::write(path, serde_json::to_string(config).unwrap()).unwrap();
+    }
+    
+    fn named(name: &str) -> AppConfig {
+        let mut config = AppConfig::default();
+        config.servers[0].name = name.to_string();
+        config
+    }
+    
+    #[test]
+    fn writes_atomically_without_leftovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = ConfigManager::load(dir.path().to_path_buf());
+        manager.config.write().servers[0].name = "香港".to_string();
+        manager.write_atomic().unwrap();
+        
+        let (config, version, _) = ConfigManager::load_from_path(&dir.path().join("config.json"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(config.servers[0].name, "香港");
+        assert!(!dir.path().join("config.json.tmp").exists());
+    }
+    
+    #[test]
+    fn backs_up_at_most_once_per_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        write_config(&dir.path().join("config.json"), &named("a"));
+        let manager = ConfigManager::load(dir.path().to_path_buf());
+        
+        manager.save().unwrap();
+        manager.save().unwrap();
+        assert_eq!(manager.backups().len(), 1);
+        
+        // Once the interval passed, the next save backs up again
+        *manager.last_backup.lock() = Instant::now().checked_sub(BACKUP_INTERVAL);
+        fs::remove_file(&manager.backups()[0]).unwrap();
+        manager.save().unwrap();
+        assert_eq!(manager.backups().len(), 1);
+    }
+    
+    #[test]
+    fn prunes_backups_beyond_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = dir.path().join("backups");
+        fs::create_dir_all(&backup_dir).unwrap();
+        for day in 1..=MAX_BACKUPS + 2 {
+            write_config(&backup_dir.join(format!("config-200001{:02}-000000.json", day)), &named("old"));
+        }
+        write_config(&dir.path().join("config.json"), &named("current"));
+        
+        let manager = ConfigManager::load(dir.path().to_path_buf());
+        manager.save().unwrap();
+        
+        let backups = manager.backups();
+        assert_eq!(backups.len(), MAX_BACKUPS);
+        // The new backup is the newest; the two oldest were removed
+        let (newest, _, _) = ConfigManager::load_from_path(&backups[0]).unwrap().unwrap();
+        assert_eq!(newest.servers[0].name, "current");
+        assert!(!backup_dir.join("config-20000103-000000.json").exists());
+        assert!(backup_dir.join("config-20000104-000000.json").exists());
+    }
+    
+    #[test]
+    fn restores_newest_valid_backup_when_corrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = dir.path().join("backups");
+        fs::create_dir_all(&backup_dir).unwrap();
+        write_config(&backup_dir.join("config-20000101-000000.json"), &named("older"));
+        write_config(&backup_dir.join("config-20000102-000000.json"), &named("newest"));
+        fs::write(backup_dir.join("config-20000103-000000.json"), "{ truncated").unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, "{ \"servers\": [").unwrap();
+        
+        let manager = ConfigManager::load(dir.path().to_path_buf());
+        assert_eq!(manager.get_servers()[0].name, "newest");
+        
+        let recovery = manager.recovery().unwrap();
+        assert!(!recovery.read_only);
+        assert!(recovery.restored_from.as_deref().unwrap().ends_with("config-20000102-000000.json"));
+        let quarantined = PathBuf::from(recovery.quarantined.as_deref().unwrap());
+        assert_eq!(fs::read_to_string(quarantined).unwrap(), "{ \"servers\": [");
+        
+        // A readable file was put back
+        let (config, _, _) = ConfigManager::load_from_path(&path).unwrap().unwrap();
+        assert_eq!(config.servers[0].name, "newest");
+    }
+    
+    #[test]
+    fn resets_when_no_backup_is_valid() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("config.json"), "not json").unwrap();
+        
+        let manager = ConfigManager::load(dir.path().to_path_buf());
+        let recovery = manager.recovery().unwrap();
+        assert!(recovery.quarantined.is_some());
+        assert!(recovery.restored_from.is_none());
+        assert_eq!(manager.get_servers().len(), 1);
+    }
+    
+    #[test]
+    fn leaves_newer_version_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let newer = format!(
+            r#"{{"schema_version": {}, "servers": [], "current_server_id": null}}"#,
+            CURRENT_SCHEMA_VERSION + 1
+        );
+        fs::write(&path, &newer).unwrap();
+        
+        let manager = ConfigManager::load(dir.path().to_path_buf());
+        let recovery = manager.recovery().unwrap();
+        assert!(recovery.read_only);
+        assert!(recovery.quarantined.is_none());
+        assert!(manager.save().is_err());
+        
+        assert_eq!(fs::read_to_string(&path).unwrap(), newer);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+    
+    #[test]
+    fn repairs_unknown_routing_mode_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let fixture = include_str!("../fixtures/config/v2-unknown-routing.json");
+        fs::write(&path, fixture).unwrap();
+        
+        let manager = ConfigManager::load(dir.path().to_path_buf());
+        let server = &manager.get_servers()[0];
+        assert_eq!(server.name, "手动编辑");
+        assert_eq!(server.routing_mode, RoutingMode::Global);
+        
+        let recovery = manager.recovery().unwrap();
+        assert!(recovery.error.is_none());
+        assert!(!recovery.read_only);
+        assert_eq!(recovery.repaired.len(), 1);
+        assert!(recovery.repaired[0].contains("Bypass_CN"));
+        
+        // The repaired file is written back, the original kept as a backup
+        let (config, _, repaired) = ConfigManager::load_from_path(&path).unwrap().unwrap();
+        assert_eq!(config.servers[0].routing_mode, RoutingMode::Global);
+        assert!(repaired.is_empty());
+        assert_eq!(fs::read_dir(dir.path().join("backups")).unwrap().count(), 1);
+    }
+    
+    #[test]
+    fn rejects_unknown_routing_mode_outside_the_config_file() {
+        let mut server = serde_json::to_value(Server::default()).unwrap();
+        server["routing_mode"] = Value::from("Bypass_CN");
+        assert!(serde_json::from_value::<Server>(server).is_err());
+    }
+}
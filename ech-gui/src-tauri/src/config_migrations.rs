@@ -0,0 +1,226 @@
+//! Schema versions of the config file
+//! Older files are upgraded step by step on the raw JSON before they are
+//! deserialized, so a migration can rename, split or retype fields
+
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Upgrades a config object from one version to the next
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` upgrades version `n` to `n + 1`
+const MIGRATIONS: &[Migration] = &[pin_routing_mode, replace_unknown_routing_mode];
+
+/// Version written by this build
+pub const CURRENT_SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Why a config could not be upgraded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationError {
+    /// Written by a newer build with this schema version. The file is
+    /// fine, this build just can't read it.
+    NewerVersion(u32),
+    /// The file is malformed
+    Invalid(String),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::NewerVersion(version) => write!(
+                f,
+                "配置文件版本 {} 高于当前支持的版本 {}，请升级程序",
+                version, CURRENT_SCHEMA_VERSION
+            ),
+            MigrationError::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<MigrationError> for String {
+    fn from(error: MigrationError) -> Self {
+        error.to_string()
+    }
+}
+
+/// Upgrade a parsed config file to `CURRENT_SCHEMA_VERSION`, returning the
+/// version it was written with. Files without `schema_version` predate
+/// versioning and are version 0.
+pub fn migrate(config: &mut Value) -> Result<u32, MigrationError> {
+    let object = config
+        .as_object_mut()
+        .ok_or_else(|| MigrationError::Invalid("配置文件格式错误: 顶层不是对象".to_string()))?;
+    
+    let version = match object.get("schema_version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| MigrationError::Invalid(format!("配置文件版本无效: {}", v)))?,
+    };
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(MigrationError::NewerVersion(version));
+    }
+    
+    for (from, step) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        step(object)
+            .map_err(|e| MigrationError::Invalid(format!("配置文件从版本 {} 升级失败: {}", from, e)))?;
+        object.insert("schema_version".to_string(), Value::from(from as u32 + 1));
+    }
+    Ok(version)
+}
+
+/// Servers of a config object, as JSON objects
+fn servers(config: &mut Map<String, Value>) -> Result<Vec<&mut Map<String, Value>>, String> {
+    match config.get_mut("servers") {
+        None => Ok(Vec::new()),
+        Some(Value::Array(servers)) => servers
+            .iter_mut()
+            .map(|s| s.as_object_mut().ok_or_else(|| "服务器配置不是对象".to_string()))
+            .collect(),
+        Some(_) => Err("servers 不是数组".to_string()),
+    }
+}
+
+/// 0 → 1: store each server's effective routing mode. A missing value was
+/// loaded as `bypass_cn`, while an empty one was not passed to the worker,
+/// which then ran with its own default of `global`.
+fn pin_routing_mode(config: &mut Map<String, Value>) -> Result<(), String> {
+    for server in servers(config)? {
+        let mode = match server.get("routing_mode") {
+            None | Some(Value::Null) => "bypass_cn",
+            Some(Value::String(mode)) if mode.trim().is_empty() => "global",
+            Some(Value::String(_)) => continue,
+            Some(other) => return Err(format!("无效的分流模式: {}", other)),
+        };
+        server.insert("routing_mode".to_string(), Value::from(mode));
+    }
+    Ok(())
+}
+
+/// 1 → 2: routing modes became an enum. The worker ran any value it didn't
+/// know in `global` mode, so such values are replaced by `global`.
+fn replace_unknown_routing_mode(config: &mut Map<String, Value>) -> Result<(), String> {
+    const KNOWN: [&str; 3] = ["global", "bypass_cn", "none"];
+    for server in servers(config)? {
+        let known = server
+            .get("routing_mode")
+            .and_then(Value::as_str)
+            .is_some_and(|mode| KNOWN.contains(&mode));
+        if !known {
+            server.insert("routing_mode".to_string(), Value::from("global"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, RoutingMode};
+    
+    /// Migrate a fixture and deserialize it as the current config
+    fn load(fixture: &str) -> (AppConfig, u32) {
+        let mut value: Value = serde_json::from_str(fixture).unwrap();
+        let version = migrate(&mut value).unwrap();
+        (serde_json::from_value(value).unwrap(), version)
+    }
+    
+    #[test]
+    fn upgrades_unversioned_baseline() {
+        let (config, version) = load(include_str!("../fixtures/config/v0-baseline.json"));
+        assert_eq!(version, 0);
+        assert_eq!(config.schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(config.current_server_id.as_deref(), Some("3f1c0a52-0d55-4c3e-9d1b-5b7f0e6a8c21"));
+        
+        let modes: Vec<RoutingMode> = config.servers.iter().map(|s| s.routing_mode).collect();
+        assert_eq!(modes, [RoutingMode::BypassCn, RoutingMode::None, RoutingMode::Global]);
+        
+        let server = &config.servers[0];
+        assert_eq!(server.name, "香港");
+        assert_eq!(server.server, "hk.example.com:443");
+        assert_eq!(server.token, "secret");
+        assert!(server.restart_policy.enabled);
+        assert!(server.extra_args.is_empty());
+    }
+    
+    #[test]
+    fn upgrades_unversioned_with_settings() {
+        let (config, version) = load(include_str!("../fixtures/config/v0-settings.json"));
+        assert_eq!(version, 0);
+        assert_eq!(config.schema_version, CURRENT_SCHEMA_VERSION);
+        
+        let server = &config.servers[0];
+        assert_eq!(server.routing_mode, RoutingMode::BypassCn);
+        assert!(!server.restart_policy.enabled);
+        assert_eq!(server.ready_timeout_secs, 10);
+        assert_eq!(server.extra_args.get("insecure").map(String::as_str), Some("true"));
+        assert!(config.log_files.enabled);
+        assert_eq!(config.history.retention_days, 7);
+        assert_eq!(config.worker_path.as_deref(), Some("/opt/ech/ech-workers"));
+    }
+    
+    #[test]
+    fn upgrades_version_1() {
+        let (config, version) = load(include_str!("../fixtures/config/v1.json"));
+        assert_eq!(version, 1);
+        assert_eq!(config.schema_version, CURRENT_SCHEMA_VERSION);
+        
+        let modes: Vec<RoutingMode> = config.servers.iter().map(|s| s.routing_mode).collect();
+        assert_eq!(modes, [RoutingMode::Global, RoutingMode::None]);
+        assert_eq!(config.servers[1].env.get("GODEBUG").map(String::as_str), Some("netdns=go"));
+    }
+    
+    #[test]
+    fn upgrades_unknown_routing_mode_in_version_1() {
+        let (config, version) = load(include_str!("../fixtures/config/v1-unknown-routing.json"));
+        assert_eq!(version, 1);
+        assert_eq!(config.servers[0].routing_mode, RoutingMode::Global);
+    }
+    
+    #[test]
+    fn loads_version_2_unchanged() {
+        let fixture = include_str!("../fixtures/config/v2.json");
+        let (config, version) = load(fixture);
+        assert_eq!(version, 2);
+        
+        let modes: Vec<RoutingMode> = config.servers.iter().map(|s| s.routing_mode).collect();
+        assert_eq!(modes, [RoutingMode::Global, RoutingMode::None]);
+        
+        let mut value: Value = serde_json::from_str(fixture).unwrap();
+        let original = value.clone();
+        migrate(&mut value).unwrap();
+        assert_eq!(value, original);
+    }
+    
+    #[test]
+    fn current_version_needs_no_migration() {
+        let mut value = serde_json::to_value(AppConfig::default()).unwrap();
+        assert_eq!(migrate(&mut value).unwrap(), CURRENT_SCHEMA_VERSION);
+    }
+    
+    #[test]
+    fn rejects_newer_version() {
+        let mut value = serde_json::json!({
+            "schema_version": CURRENT_SCHEMA_VERSION + 1,
+            "servers": [],
+            "current_server_id": null
+        });
+        assert_eq!(
+            migrate(&mut value),
+            Err(MigrationError::NewerVersion(CURRENT_SCHEMA_VERSION + 1))
+        );
+    }
+    
+    #[test]
+    fn rejects_malformed_files() {
+        for mut value in [
+            serde_json::json!([]),
+            serde_json::json!({ "schema_version": "1" }),
+            serde_json::json!({ "servers": {} }),
+            serde_json::json!({ "servers": [{ "id": "a", "name": "a", "routing_mode": 1 }] }),
+        ] {
+            assert!(matches!(migrate(&mut value), Err(MigrationError::Invalid(_))));
+        }
+    }
+}
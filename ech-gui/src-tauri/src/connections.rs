@@ -0,0 +1,259 @@
+//! Live connection tracking for ECH Workers
+//! Pairs the worker's connect and disconnect log events into a table of
+//! tunnels that are currently open
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::worker_log::{Route, WorkerEvent};
+
+/// An open tunnel through the worker
+#[derive(Debug, Clone, Serialize)]
+pub struct Connection {
+    pub id: u64,
+    /// Server whose worker carries the tunnel
+    pub server_id: String,
+    pub client: String,
+    pub target: String,
+    pub route: Route,
+    /// Epoch milliseconds of the log line that opened the tunnel
+    pub started_at: u64,
+}
+
+/// Payload of the `connections-changed` event
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ConnectionChange {
+    Opened {
+        connection: Connection,
+    },
+    Closed {
+        connection: Connection,
+        duration_ms: u64,
+    },
+    /// All connections of a server were dropped because its worker stopped
+    Cleared { server_id: String },
+}
+
+/// Table of currently open tunnels
+pub struct ConnectionTable {
+    inner: Mutex<ConnectionTableInner>,
+}
+
+struct ConnectionTableInner {
+    open: Vec<Connection>,
+    next_id: u64,
+}
+
+impl ConnectionTable {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(ConnectionTableInner {
+                open: Vec::new(),
+                next_id: 1,
+            }),
+        }
+    }
+    
+    /// Open connections, oldest first
+    pub fn list(&self) -> Vec<Connection> {
+        self.inner.lock().open.clone()
+    }
+    
+    /// Update the table from an event of `server_id`'s worker seen at `timestamp`
+    pub fn apply(&self, server_id: &str, event: &WorkerEvent, timestamp: u64) -> Option<ConnectionChange> {
+        match event {
+            // Proxied tunnels are only open once the WebSocket is up;
+            // direct ones are logged only when the decision is made
+            WorkerEvent::Connected { client, target } => {
+                Some(self.open(server_id, client, target, Route::Proxy, timestamp))
+            }
+            WorkerEvent::RouteDecision {
+                client,
+                target,
+                route: Route::Direct,
+            } => Some(self.open(server_id, client, target, Route::Direct, timestamp)),
+            WorkerEvent::Disconnected { client, target } => {
+                self.close(server_id, client, Some(target), Some(Route::Proxy), timestamp)
+            }
+            WorkerEvent::DirectClosed { client, target } => {
+                self.close(server_id, client, Some(target), Some(Route::Direct), timestamp)
+            }
+            // A tunnel that fails after opening is not logged as closed
+            WorkerEvent::ProxyFailed { client, .. } => {
+                self.close(server_id, client, None, None, timestamp)
+            }
+            _ => None,
+        }
+    }
+    
+    /// Drop every connection of a server, returning whether any were open
+    pub fn clear(&self, server_id: &str) -> bool {
+        let mut inner = self.inner.lock();
+        let before = inner.open.len();
+        inner.open.retain(|c| c.server_id != server_id);
+        inner.open.len() != before
+    }
+    
+    fn open(
+        &self,
+        server_id: &str,
+        client: &str,
+        target: &str,
+        route: Route,
+        timestamp: u64,
+    ) -> ConnectionChange {
+        let mut inner = self.inner.lock();
+        let connection = Connection {
+            id: inner.next_id,
+            server_id: server_id.to_string(),
+            client: client.to_string(),
+            target: target.to_string(),
+            route,
+            started_at: timestamp,
+        };
+        inner.next_id += 1;
+        inner.open.push(connection.clone());
+        ConnectionChange::Opened { connection }
+    }
+    
+    fn close(
+        &self,
+        server_id: &str,
+        client: &str,
+        target: Option<&String>,
+        route: Option<Route>,
+        timestamp: u64,
+    ) -> Option<ConnectionChange> {
+        let mut inner = self.inner.lock();
+        let same_client = |c: &Connection| {
+            c.server_id == server_id && c.client == client && route.is_none_or(|r| c.route == r)
+        };
+        
+        // The worker appends a default port to direct targets that lack one,
+        // so fall back to matching on the client address alone
+        let index = inner
+            .open
+            .iter()
+            .position(|c| same_client(c) && target == Some(&c.target))
+            .or_else(|| inner.open.iter().position(same_client))?;
+        
+        let connection = inner.open.remove(index);
+        let duration_ms = timestamp.saturating_sub(connection.started_at);
+        Some(ConnectionChange::Closed {
+            connection,
+            duration_ms,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn connected(client: &str, target: &str) -> WorkerEvent {
+        WorkerEvent::Connected {
+            client: client.to_string(),
+            target: target.to_string(),
+        }
+    }
+    
+    fn disconnected(client: &str, target: &str) -> WorkerEvent {
+        WorkerEvent::Disconnected {
+            client: client.to_string(),
+            target: target.to_string(),
+        }
+    }
+    
+    fn direct(client: &str, target: &str) -> WorkerEvent {
+        WorkerEvent::RouteDecision {
+            client: client.to_string(),
+            target: target.to_string(),
+            route: Route::Direct,
+        }
+    }
+    
+    fn closed(change: Option<ConnectionChange>) -> (Connection, u64) {
+        match change {
+            Some(ConnectionChange::Closed {
+                connection,
+                duration_ms,
+            }) => (connection, duration_ms),
+            other => panic!("expected a closed connection, got {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn closes_the_matching_connection() {
+        let table = ConnectionTable::new();
+        table.apply("a", &connected("127.0.0.1:1000", "x.com:443"), 100);
+        table.apply("a", &connected("127.0.0.1:1000", "y.com:443"), 200);
+        
+        let close = disconnected("127.0.0.1:1000", "y.com:443");
+        let (connection, duration_ms) = closed(table.apply("a", &close, 1200));
+        assert_eq!(connection.target, "y.com:443");
+        assert_eq!(duration_ms, 1000);
+        
+        let open: Vec<String> = table.list().into_iter().map(|c| c.target).collect();
+        assert_eq!(open, ["x.com:443"]);
+    }
+    
+    #[test]
+    fn falls_back_to_the_client_when_the_target_differs() {
+        let table = ConnectionTable::new();
+        table.apply("a", &direct("127.0.0.1:1000", "baidu.com"), 100);
+        
+        let close = WorkerEvent::DirectClosed {
+            client: "127.0.0.1:1000".to_string(),
+            target: "baidu.com:443".to_string(),
+        };
+        let (connection, _) = closed(table.apply("a", &close, 150));
+        assert_eq!(connection.route, Route::Direct);
+        assert!(table.list().is_empty());
+    }
+    
+    #[test]
+    fn ignores_closing_unknown_connections() {
+        let table = ConnectionTable::new();
+        table.apply("a", &connected("127.0.0.1:1000", "x.com:443"), 100);
+        
+        // Unknown client, another server's worker, and the wrong route
+        assert!(table.apply("a", &disconnected("127.0.0.1:2000", "x.com:443"), 200).is_none());
+        assert!(table.apply("b", &disconnected("127.0.0.1:1000", "x.com:443"), 200).is_none());
+        let direct_closed = WorkerEvent::DirectClosed {
+            client: "127.0.0.1:1000".to_string(),
+            target: "x.com:443".to_string(),
+        };
+        assert!(table.apply("a", &direct_closed, 200).is_none());
+        assert_eq!(table.list().len(), 1);
+        
+        // Closing twice only closes once
+        closed(table.apply("a", &disconnected("127.0.0.1:1000", "x.com:443"), 300));
+        assert!(table.apply("a", &disconnected("127.0.0.1:1000", "x.com:443"), 400).is_none());
+    }
+    
+    #[test]
+    fn proxy_failure_closes_any_route() {
+        let table = ConnectionTable::new();
+        table.apply("a", &direct("127.0.0.1:1000", "baidu.com:443"), 100);
+        
+        let failed = WorkerEvent::ProxyFailed {
+            protocol: "SOCKS5".to_string(),
+            client: "127.0.0.1:1000".to_string(),
+            error: "EOF".to_string(),
+        };
+        closed(table.apply("a", &failed, 200));
+        assert!(table.list().is_empty());
+    }
+    
+    #[test]
+    fn clear_only_drops_the_servers_connections() {
+        let table = ConnectionTable::new();
+        table.apply("a", &connected("127.0.0.1:1000", "x.com:443"), 100);
+        table.apply("b", &connected("127.0.0.1:2000", "x.com:443"), 100);
+        
+        assert!(table.clear("a"));
+        assert!(!table.clear("a"));
+        assert_eq!(table.list()[0].server_id, "b");
+    }
+}
@@ -0,0 +1,557 @@
+//! Connection history for ECH Workers
+//! Records completed tunnels in a SQLite database under the config
+//! directory and answers aggregate queries over them
+
+use parking_lot::Mutex;
+use rusqlite::{params, Connection as Db};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{ConfigManager, HistorySettings};
+use crate::connections::Connection;
+use crate::logs::now_millis;
+use crate::worker_log::Route;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS tunnels (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at  INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    host        TEXT NOT NULL,
+    port        INTEGER,
+    route       TEXT NOT NULL,
+    server_id   TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_tunnels_started_at ON tunnels(started_at);
+CREATE INDEX IF NOT EXISTS idx_tunnels_host ON tunnels(host);
+";
+
+/// Readers don't block the writer, and commits don't wait for a full sync.
+/// A crash may lose the last commits, which is fine for statistics.
+const PRAGMAS: &str = "
+PRAGMA journal_mode = WAL;
+PRAGMA synchronous = NORMAL;
+";
+
+/// How long the writer collects tunnels before committing them together
+const BATCH_DELAY: Duration = Duration::from_millis(500);
+
+/// Most tunnels written in one transaction
+const MAX_BATCH: usize = 500;
+
+/// How often old rows are pruned while recording
+const PRUNE_INTERVAL_MS: u64 = 60 * 60 * 1000;
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// Time range for history queries, in epoch milliseconds (inclusive)
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct HistoryRange {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+impl HistoryRange {
+    fn bounds(&self) -> (i64, i64) {
+        (
+            self.since.map_or(0, |t| t as i64),
+            self.until.map_or(i64::MAX, |t| t as i64),
+        )
+    }
+}
+
+/// Aggregate for one destination host
+#[derive(Debug, Clone, Serialize)]
+pub struct DestinationStat {
+    pub host: String,
+    pub sessions: u64,
+    pub proxied: u64,
+    pub direct: u64,
+    pub total_duration_ms: u64,
+}
+
+/// Split of tunnels between the proxy and direct connections
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteRatio {
+    pub proxied: u64,
+    pub direct: u64,
+    /// Share of proxied tunnels between 0 and 1, or 0 when there are none
+    pub proxied_ratio: f64,
+}
+
+/// Number of tunnels for one server on one local calendar day
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerDayStat {
+    pub server_id: String,
+    /// Local date as `YYYY-MM-DD`
+    pub day: String,
+    pub sessions: u64,
+}
+
+/// A closed tunnel waiting to be written
+struct Tunnel {
+    started_at: u64,
+    duration_ms: u64,
+    host: String,
+    port: Option<u16>,
+    route: &'static str,
+    server_id: String,
+}
+
+struct HistoryState {
+    settings: HistorySettings,
+    db: Option<Db>,
+    last_prune: u64,
+}
+
+/// State shared with the writer thread
+struct Shared {
+    path: PathBuf,
+    state: Mutex<HistoryState>,
+    /// Error of the last background write, reported by the next `record`
+    write_error: Mutex<Option<String>>,
+}
+
+/// SQLite-backed store of completed tunnels, opened on first use. Tunnels
+/// are written by a background thread in batches, so recording them
+/// doesn't hold up the log reader.
+pub struct HistoryStore {
+    shared: Arc<Shared>,
+    /// Queue of the writer thread, started with the first record
+    writer: Mutex<Option<Sender<Tunnel>>>,
+}
+
+impl HistoryStore {
+    pub fn new() -> Self {
+        Self::at(ConfigManager::get_config_dir().join("history.db"))
+    }
+    
+    /// Create a store backed by the database at `path`
+    fn at(path: PathBuf) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                path,
+                state: Mutex::new(HistoryState {
+                    settings: HistorySettings::default(),
+                    db: None,
+                    last_prune: 0,
+                }),
+                write_error: Mutex::new(None),
+            }),
+            writer: Mutex::new(None),
+        }
+    }
+    
+    /// Apply new settings; the retention limit is enforced on the next write
+    pub fn configure(&self, settings: HistorySettings) {
+        let mut state = self.shared.state.lock();
+        state.settings = settings;
+        state.last_prune = 0;
+    }
+    
+    /// Queue a closed tunnel for writing. Returns the error of an earlier
+    /// write, as the writer has no one else to report it to.
+    pub fn record(&self, connection: &Connection, duration_ms: u64) -> Result<(), String> {
+        let (host, port) = split_target(&connection.target);
+        let route = match connection.route {
+            Route::Proxy => "proxy",
+            Route::Direct => "direct",
+        };
+        let tunnel = Tunnel {
+            started_at: connection.started_at,
+            duration_ms,
+            host,
+            port,
+            route,
+            server_id: connection.server_id.clone(),
+        };
+        
+        let mut writer = self.writer.lock();
+        let sender = writer.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel();
+            let shared = Arc::clone(&self.shared);
+            thread::spawn(move || shared.run_writer(rx));
+            tx
+        });
+        if sender.send(tunnel).is_err() {
+            *writer = None;
+            return Err("写入连接历史失败: 写入线程已退出".to_string());
+        }
+        drop(writer);
+        
+        match self.shared.write_error.lock().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+    
+    /// Most visited destination hosts, by number of tunnels
+    pub fn top_destinations(&self, range: HistoryRange, limit: usize) -> Result<Vec<DestinationStat>, String> {
+        let (since, until) = range.bounds();
+        let mut state = self.shared.state.lock();
+        let db = self.shared.db(&mut state)?;
+        let mut stmt = db
+            .prepare(
+                "SELECT host,
+                        COUNT(*),
+                        SUM(route = 'proxy'),
+                        SUM(route = 'direct'),
+                        SUM(duration_ms)
+                 FROM tunnels
+                 WHERE started_at BETWEEN ?1 AND ?2
+                 GROUP BY host
+                 ORDER BY COUNT(*) DESC, host
+                 LIMIT ?3",
+            )
+            .map_err(query_error)?;
+        let rows = stmt
+            .query_map(params![since, until, limit as i64], |row| {
+                Ok(DestinationStat {
+                    host: row.get(0)?,
+                    sessions: row.get::<_, i64>(1)? as u64,
+                    proxied: row.get::<_, i64>(2)? as u64,
+                    direct: row.get::<_, i64>(3)? as u64,
+                    total_duration_ms: row.get::<_, i64>(4)? as u64,
+                })
+            })
+            .map_err(query_error)?;
+        rows.collect::<Result<_, _>>().map_err(query_error)
+    }
+    
+    /// Proxied versus direct tunnel counts
+    pub fn route_ratio(&self, range: HistoryRange) -> Result<RouteRatio, String> {
+        let (since, until) = range.bounds();
+        let mut state = self.shared.state.lock();
+        let db = self.shared.db(&mut state)?;
+        let (proxied, direct) = db
+            .query_row(
+                "SELECT COALESCE(SUM(route = 'proxy'), 0), COALESCE(SUM(route = 'direct'), 0)
+                 FROM tunnels
+                 WHERE started_at BETWEEN ?1 AND ?2",
+                params![since, until],
+                |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64)),
+            )
+            .map_err(query_error)?;
+        
+        let total = proxied + direct;
+        Ok(RouteRatio {
+            proxied,
+            direct,
+            proxied_ratio: if total == 0 { 0.0 } else { proxied as f64 / total as f64 },
+        })
+    }
+    
+    /// Tunnels per server per local day, newest day first
+    pub fn sessions_per_server_per_day(&self, range: HistoryRange) -> Result<Vec<ServerDayStat>, String> {
+        let (since, until) = range.bounds();
+        let mut state = self.shared.state.lock();
+        let db = self.shared.db(&mut state)?;
+        let mut stmt = db
+            .prepare(
+                "SELECT server_id,
+                        date(started_at / 1000, 'unixepoch', 'localtime') AS day,
+                        COUNT(*)
+                 FROM tunnels
+                 WHERE started_at BETWEEN ?1 AND ?2
+                 GROUP BY server_id, day
+                 ORDER BY day DESC, server_id",
+            )
+            .map_err(query_error)?;
+        let rows = stmt
+            .query_map(params![since, until], |row| {
+                Ok(ServerDayStat {
+                    server_id: row.get(0)?,
+                    day: row.get(1)?,
+                    sessions: row.get::<_, i64>(2)? as u64,
+                })
+            })
+            .map_err(query_error)?;
+        rows.collect::<Result<_, _>>().map_err(query_error)
+    }
+    
+    /// Delete all recorded history
+    pub fn clear(&self) -> Result<(), String> {
+        let mut state = self.shared.state.lock();
+        let db = self.shared.db(&mut state)?;
+        db.execute("DELETE FROM tunnels", [])
+            .map_err(|e| format!("清空连接历史失败: {}", e))?;
+        Ok(())
+    }
+}
+
+impl Shared {
+    /// Write queued tunnels until the store is dropped, committing what
+    /// arrives within `BATCH_DELAY` of the first tunnel together
+    fn run_writer(&self, rx: Receiver<Tunnel>) {
+        while let Ok(first) = rx.recv() {
+            let mut batch = vec![first];
+            let deadline = Instant::now() + BATCH_DELAY;
+            while batch.len() < MAX_BATCH {
+                match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(tunnel) => batch.push(tunnel),
+                    Err(_) => break,
+                }
+            }
+            if let Err(e) = self.write(&batch) {
+                *self.write_error.lock() = Some(e);
+            }
+        }
+    }
+    
+    /// Insert tunnels in one transaction, pruning old rows when due
+    fn write(&self, batch: &[Tunnel]) -> Result<(), String> {
+        let mut state = self.state.lock();
+        if !state.settings.enabled {
+            return Ok(());
+        }
+        
+        let now = now_millis();
+        let prune_due = now.saturating_sub(state.last_prune) >= PRUNE_INTERVAL_MS;
+        if prune_due {
+            state.last_prune = now;
+        }
+        let retention_days = state.settings.retention_days;
+        let write_error = |e: rusqlite::Error| format!("写入连接历史失败: {}", e);
+        
+        let db = self.db(&mut state)?;
+        let tx = db.transaction().map_err(write_error)?;
+        {
+            let mut insert = tx
+                .prepare_cached(
+                    "INSERT INTO tunnels (started_at, duration_ms, host, port, route, server_id)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                )
+                .map_err(write_error)?;
+            for tunnel in batch {
+                insert
+                    .execute(params![
+                        tunnel.started_at as i64,
+                        tunnel.duration_ms as i64,
+                        tunnel.host,
+                        tunnel.port,
+                        tunnel.route,
+                        tunnel.server_id
+                    ])
+                    .map_err(write_error)?;
+            }
+        }
+        if prune_due {
+            let cutoff = now.saturating_sub(retention_days.saturating_mul(DAY_MS));
+            tx.execute("DELETE FROM tunnels WHERE started_at < ?1", params![cutoff as i64])
+                .map_err(|e| format!("清理连接历史失败: {}", e))?;
+        }
+        tx.commit().map_err(write_error)
+    }
+    
+    /// Open the database on first use
+    fn db<'a>(&self, state: &'a mut HistoryState) -> Result<&'a mut Db, String> {
+        if state.db.is_none() {
+            if let Some(dir) = self.path.parent() {
+                std::fs::create_dir_all(dir).ok();
+            }
+            let db = Db::open(&self.path).map_err(|e| format!("打开连接历史数据库失败: {}", e))?;
+            db.execute_batch(PRAGMAS)
+                .and_then(|()| db.execute_batch(SCHEMA))
+                .map_err(|e| format!("初始化连接历史数据库失败: {}", e))?;
+            state.db = Some(db);
+        }
+        Ok(state.db.as_mut().expect("database opened above"))
+    }
+}
+
+fn query_error(e: rusqlite::Error) -> String {
+    format!("查询连接历史失败: {}", e)
+}
+
+/// Split a worker target into host and port. IPv6 hosts lose their brackets.
+fn split_target(target: &str) -> (String, Option<u16>) {
+    if let Some((host, port)) = target.rsplit_once(':') {
+        if let Ok(port) = port.parse::<u16>() {
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            return (host.to_string(), Some(port));
+        }
+    }
+    (target.to_string(), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+    
+    fn store(dir: &tempfile::TempDir) -> HistoryStore {
+        HistoryStore::at(dir.path().join("history.db"))
+    }
+    
+    fn connection(target: &str, route: Route, started_at: u64) -> Connection {
+        Connection {
+            id: 0,
+            server_id: "s1".to_string(),
+            client: "127.0.0.1:50000".to_string(),
+            target: target.to_string(),
+            route,
+            started_at,
+        }
+    }
+    
+    fn tunnel(host: &str, route: &'static str, started_at: u64, server_id: &str) -> Tunnel {
+        Tunnel {
+            started_at,
+            duration_ms: 1000,
+            host: host.to_string(),
+            port: Some(443),
+            route,
+            server_id: server_id.to_string(),
+        }
+    }
+    
+    /// Wait for the writer thread to commit `count` tunnels
+    fn wait_for_rows(store: &HistoryStore, count: u64) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let ratio = store.route_ratio(HistoryRange::default()).unwrap();
+            if ratio.proxied + ratio.direct >= count {
+                return;
+            }
+            assert!(Instant::now() < deadline, "tunnels were not written");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+    
+    fn local_millis(y: i32, m: u32, d: u32, h: u32, min: u32) -> u64 {
+        Local.with_ymd_and_hms(y, m, d, h, min, 0).unwrap().timestamp_millis() as u64
+    }
+    
+    #[test]
+    fn writer_flushes_recorded_tunnels() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+        let now = now_millis();
+        store.record(&connection("example.com:443", Route::Proxy, now), 10).unwrap();
+        store.record(&connection("[::1]:8080", Route::Direct, now), 20).unwrap();
+        store.record(&connection("example.com:443", Route::Proxy, now), 30).unwrap();
+        wait_for_rows(&store, 3);
+        
+        let top = store.top_destinations(HistoryRange::default(), 10).unwrap();
+        assert_eq!(top[0].host, "example.com");
+        assert_eq!(top[0].total_duration_ms, 40);
+        assert_eq!(top[1].host, "::1");
+    }
+    
+    #[test]
+    fn prunes_tunnels_past_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+        store.configure(HistorySettings {
+            enabled: true,
+            retention_days: 1,
+        });
+        let now = now_millis();
+        store.record(&connection("old.example:443", Route::Proxy, now - 2 * DAY_MS), 0).unwrap();
+        store.record(&connection("new.example:443", Route::Proxy, now), 0).unwrap();
+        wait_for_rows(&store, 1);
+        
+        let hosts: Vec<String> = store
+            .top_destinations(HistoryRange::default(), 10)
+            .unwrap()
+            .into_iter()
+            .map(|d| d.host)
+            .collect();
+        assert_eq!(hosts, ["new.example"]);
+    }
+    
+    #[test]
+    fn ranks_destinations_within_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+        let now = now_millis();
+        store
+            .shared
+            .write(&[
+                tunnel("a.example", "proxy", now, "s1"),
+                tunnel("a.example", "direct", now, "s1"),
+                tunnel("a.example", "proxy", now, "s2"),
+                tunnel("b.example", "proxy", now, "s1"),
+                tunnel("b.example", "proxy", now - 1000, "s1"),
+                tunnel("b.example", "proxy", now - 2000, "s1"),
+            ])
+            .unwrap();
+        
+        let recent = HistoryRange {
+            since: Some(now),
+            until: None,
+        };
+        let top = store.top_destinations(recent, 1).unwrap();
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].host, "a.example");
+        assert_eq!((top[0].sessions, top[0].proxied, top[0].direct), (3, 2, 1));
+        assert_eq!(top[0].total_duration_ms, 3000);
+        
+        let top = store.top_destinations(HistoryRange::default(), 10).unwrap();
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].host, "a.example");
+        assert_eq!(top[1].sessions, 3);
+    }
+    
+    #[test]
+    fn computes_route_ratio() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+        let ratio = store.route_ratio(HistoryRange::default()).unwrap();
+        assert_eq!((ratio.proxied, ratio.direct, ratio.proxied_ratio), (0, 0, 0.0));
+        
+        let now = now_millis();
+        store
+            .shared
+            .write(&[
+                tunnel("a.example", "proxy", now, "s1"),
+                tunnel("a.example", "proxy", now, "s1"),
+                tunnel("a.example", "proxy", now, "s1"),
+                tunnel("b.example", "direct", now, "s1"),
+            ])
+            .unwrap();
+        let ratio = store.route_ratio(HistoryRange::default()).unwrap();
+        assert_eq!((ratio.proxied, ratio.direct), (3, 1));
+        assert_eq!(ratio.proxied_ratio, 0.75);
+    }
+    
+    #[test]
+    fn groups_sessions_by_local_day() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+        // Keep the fixed dates from being pruned
+        store.configure(HistorySettings {
+            enabled: true,
+            retention_days: 100 * 365,
+        });
+        store
+            .shared
+            .write(&[
+                tunnel("a.example", "proxy", local_millis(2024, 3, 9, 0, 5), "s1"),
+                tunnel("a.example", "proxy", local_millis(2024, 3, 9, 23, 55), "s1"),
+                tunnel("a.example", "proxy", local_millis(2024, 3, 10, 0, 5), "s1"),
+                tunnel("a.example", "proxy", local_millis(2024, 3, 10, 12, 0), "s2"),
+            ])
+            .unwrap();
+        
+        let days: Vec<(String, String, u64)> = store
+            .sessions_per_server_per_day(HistoryRange::default())
+            .unwrap()
+            .into_iter()
+            .map(|d| (d.day, d.server_id, d.sessions))
+            .collect();
+        assert_eq!(
+            days,
+            [
+                ("2024-03-10".to_string(), "s1".to_string(), 1),
+                ("2024-03-10".to_string(), "s2".to_string(), 1),
+                ("2024-03-09".to_string(), "s1".to_string(), 2),
+            ]
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Integrity verification of the ech-workers executable
+//! The worker receives the auth token, so a binary substituted somewhere
+//! on the search path must not run unnoticed
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::{IntegrityMode, IntegritySettings};
+use crate::worker_binary::BinarySource;
+
+/// Hashes of official release binaries, bundled with the app
+const BUNDLED_HASHES: &str = include_str!("../worker-hashes.txt");
+
+/// Outcome of comparing the executable against the trusted hashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationState {
+    /// The hash is trusted
+    Verified,
+    /// The hash matches none of the trusted hashes
+    Mismatch,
+    /// There are no trusted hashes to compare against
+    Unpinned,
+    /// Verification is turned off
+    Skipped,
+}
+
+/// The executable's hash and verification state
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrityReport {
+    pub path: PathBuf,
+    pub source: BinarySource,
+    pub sha256: String,
+    pub mode: IntegrityMode,
+    pub state: VerificationState,
+}
+
+impl IntegrityReport {
+    /// Check the executable against the settings
+    pub fn new(path: PathBuf, source: BinarySource, settings: &IntegritySettings) -> Result<Self, String> {
+        let sha256 = sha256_file(&path)
+            .map_err(|e| format!("无法读取 ech-workers ({}): {}", path.display(), e))?;
+        
+        let trusted = trusted_hashes(settings);
+        let state = if settings.mode == IntegrityMode::Off {
+            VerificationState::Skipped
+        } else if trusted.is_empty() {
+            VerificationState::Unpinned
+        } else if trusted.contains(&sha256) {
+            VerificationState::Verified
+        } else {
+            VerificationState::Mismatch
+        };
+        
+        Ok(Self {
+            path,
+            source,
+            sha256,
+            mode: settings.mode,
+            state,
+        })
+    }
+    
+    /// Hash the executable again right before it is run, so a file swapped
+    /// after it was verified doesn't receive the token
+    pub fn recheck(&self) -> Result<(), String> {
+        if self.state == VerificationState::Skipped {
+            return Ok(());
+        }
+        let sha256 = sha256_file(&self.path)
+            .map_err(|e| format!("无法读取 ech-workers ({}): {}", self.path.display(), e))?;
+        if sha256 != self.sha256 {
+            return Err(format!(
+                "ech-workers ({}) 在校验后被修改，已拒绝启动",
+                self.path.display()
+            ));
+        }
+        Ok(())
+    }
+    
+    /// Whether the executable may run. Returns a warning to log when it
+    /// may run but is not verified.
+    pub fn check(&self) -> Result<Option<String>, String> {
+        let problem = match self.state {
+            VerificationState::Verified | VerificationState::Skipped => return Ok(None),
+            VerificationState::Mismatch => format!(
+                "ech-workers ({}) 的 SHA-256 {} 不在可信列表中",
+                self.path.display(),
+                self.sha256
+            ),
+            VerificationState::Unpinned => format!(
+                "未配置可信的 ech-workers 哈希，无法校验 {} (SHA-256 {})",
+                self.path.display(),
+                self.sha256
+            ),
+        };
+        match self.mode {
+            IntegrityMode::Enforce => Err(format!("{}，已拒绝启动", problem)),
+            _ => Ok(Some(problem)),
+        }
+    }
+}
+
+/// Hashes listed in `worker-hashes.txt`, without comments
+fn bundled_hashes() -> impl Iterator<Item = &'static str> {
+    BUNDLED_HASHES
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|h| !h.is_empty())
+}
+
+/// Whether this build ships hashes of release binaries. Without them
+/// verification is off by default, as there is nothing to compare with.
+pub fn has_bundled_hashes() -> bool {
+    bundled_hashes().next().is_some()
+}
+
+/// Bundled and configured hashes, lowercased
+fn trusted_hashes(settings: &IntegritySettings) -> BTreeSet<String> {
+    let configured = settings.trusted_hashes.iter().map(|h| h.trim()).filter(|h| !h.is_empty());
+    bundled_hashes()
+        .map(|h| h.to_ascii_lowercase())
+        .chain(configured.map(|h| h.to_ascii_lowercase()))
+        .collect()
+}
+
+/// SHA-256 of a file as lowercase hex
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
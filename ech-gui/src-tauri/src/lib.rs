@@ -3,8 +3,23 @@
 //! This is the main library that connects all modules and initializes Tauri.
 
 mod config;
+mod config_migrations;
+mod connections;
+mod history;
+mod integrity;
+mod log_files;
+mod logs;
+mod orphans;
+mod port_check;
 mod process;
+mod process_stats;
 mod proxy;
+mod server_fields;
+mod server_transfer;
+mod supervisor;
+mod worker_args;
+mod worker_binary;
+mod worker_log;
 mod commands;
 
 use commands::*;
@@ -22,6 +37,10 @@ pub fn run() {
         .plugin(tauri_plugin_process::init())
         .plugin(tauri_plugin_notification::init())
         .setup(|app| {
+            // A crashed previous instance may have left workers holding ports
+            cleanup_leftover_workers(app.handle());
+            report_config_recovery(app.handle());
+            
             // Create tray menu
             let show_item = MenuItem::with_id(app, "show", "显示窗口", true, None::<&str>)?;
             let hide_item = MenuItem::with_id(app, "hide", "隐藏窗口", true, None::<&str>)?;
@@ -48,11 +67,13 @@ pub fn run() {
                             }
                         }
                         "quit" => {
-                            // Clean up before quitting
+                            // Clean up before quitting, off the event loop
                             let app_handle = app.clone();
-                            let _ = stop_process(app_handle);
-                            let _ = set_system_proxy(false);
-                            app.exit(0);
+                            tauri::async_runtime::spawn(async move {
+                                let _ = stop_all_processes(app_handle.clone()).await;
+                                let _ = set_system_proxy(false);
+                                app_handle.exit(0);
+                            });
                         }
                         _ => {}
                     }
@@ -91,15 +112,48 @@ pub fn run() {
             get_servers,
             get_current_server,
             get_current_server_id,
+            get_config_recovery,
             set_current_server,
             add_server,
             update_server,
+            validate_server,
+            get_routing_modes,
+            export_servers,
+            preview_import_servers,
+            import_servers,
             delete_server,
             rename_server,
+            parse_worker_command,
             // Process commands
             start_process,
+            switch_server,
             stop_process,
+            stop_all_processes,
             is_process_running,
+            get_running_workers,
+            get_process_stats,
+            get_logs,
+            get_connections,
+            // Worker executable commands
+            get_worker_path,
+            set_worker_path,
+            get_worker_binary,
+            get_integrity_settings,
+            set_integrity_settings,
+            get_worker_integrity,
+            // Log file commands
+            get_log_file_settings,
+            set_log_file_settings,
+            list_log_files,
+            open_log_file,
+            delete_log_file,
+            // History commands
+            get_history_settings,
+            set_history_settings,
+            get_top_destinations,
+            get_route_ratio,
+            get_server_daily_sessions,
+            clear_history,
             // Proxy commands
             set_system_proxy,
             get_proxy_status,
@@ -0,0 +1,338 @@
+//! Persistent log files for ECH Workers
+//! Writes worker output to rotating files under the config directory,
+//! one file per worker session and server
+
+use chrono::Local;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use crate::config::{ConfigManager, LogFileSettings};
+use crate::logs::LogEntry;
+
+const FILE_PREFIX: &str = "ech-workers-";
+const FILE_SUFFIX: &str = ".log";
+
+/// A log file on disk, as listed to the frontend
+#[derive(Debug, Clone, Serialize)]
+pub struct LogFileInfo {
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    /// Last modification time in epoch milliseconds
+    pub modified: u64,
+    /// Whether a worker is currently writing to this file
+    pub active: bool,
+}
+
+struct CurrentFile {
+    file: File,
+    path: PathBuf,
+    size: u64,
+    opened: Instant,
+}
+
+struct LogFilesState {
+    settings: LogFileSettings,
+    /// Open file per server id
+    current: HashMap<String, CurrentFile>,
+}
+
+/// Rotating log file writer
+pub struct LogFiles {
+    dir: PathBuf,
+    state: Mutex<LogFilesState>,
+}
+
+impl LogFiles {
+    pub fn new() -> Self {
+        Self::at(ConfigManager::get_config_dir().join("logs"))
+    }
+    
+    /// Create a writer for log files in `dir`
+    fn at(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            state: Mutex::new(LogFilesState {
+                settings: LogFileSettings::default(),
+                current: HashMap::new(),
+            }),
+        }
+    }
+    
+    /// Apply new settings; disabling closes the open files
+    pub fn configure(&self, settings: LogFileSettings) {
+        let mut state = self.state.lock();
+        if !settings.enabled {
+            state.current.clear();
+        }
+        state.settings = settings;
+    }
+    
+    /// Close the server's current file so its next line starts a new one
+    pub fn begin_session(&self, server_id: &str) {
+        self.state.lock().current.remove(server_id);
+    }
+    
+    /// Append an entry to its server's current file, rotating if needed
+    pub fn write(&self, entry: &LogEntry) {
+        let mut state = self.state.lock();
+        if !state.settings.enabled {
+            return;
+        }
+        
+        let max_size = state.settings.max_file_size_kb.saturating_mul(1024);
+        let max_age = Duration::from_secs(state.settings.max_file_age_hours.saturating_mul(3600));
+        let needs_rotation = state
+            .current
+            .get(&entry.server_id)
+            .is_none_or(|c| c.size >= max_size || c.opened.elapsed() >= max_age);
+        
+        if needs_rotation {
+            state.current.remove(&entry.server_id);
+            match self.create_file(&entry.server_id) {
+                Ok(current) => {
+                    state.current.insert(entry.server_id.clone(), current);
+                }
+                Err(_) => return,
+            }
+            self.prune(state.settings.max_files, &state.current);
+        }
+        
+        if let Some(current) = state.current.get_mut(&entry.server_id) {
+            let time = chrono::DateTime::from_timestamp_millis(entry.timestamp as i64)
+                .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+                .unwrap_or_default();
+            let line = format!("{} [{}] {}\n", time, entry.stream.as_str(), entry.line);
+            if current.file.write_all(line.as_bytes()).is_ok() {
+                current.size += line.len() as u64;
+            }
+        }
+    }
+    
+    /// List log files, newest first
+    pub fn list(&self) -> Vec<LogFileInfo> {
+        let state = self.state.lock();
+        self.files(&state.current)
+    }
+    
+    fn files(&self, current: &HashMap<String, CurrentFile>) -> Vec<LogFileInfo> {
+        let mut files: Vec<LogFileInfo> = self
+            .log_paths()
+            .into_iter()
+            .filter_map(|path| {
+                let meta = fs::metadata(&path).ok()?;
+                let modified = meta
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0);
+                Some(LogFileInfo {
+                    name: path.file_name()?.to_string_lossy().to_string(),
+                    path: path.to_string_lossy().to_string(),
+                    size: meta.len(),
+                    modified,
+                    active: current.values().any(|c| c.path == path),
+                })
+            })
+            .collect();
+        files.sort_by(|a, b| b.modified.cmp(&a.modified).then(b.name.cmp(&a.name)));
+        files
+    }
+    
+    /// Resolve a file name from `list` to its path, rejecting anything
+    /// outside the log directory
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, String> {
+        let valid = name.starts_with(FILE_PREFIX)
+            && name.ends_with(FILE_SUFFIX)
+            && !name.contains(['/', '\\'])
+            && !name.contains("..");
+        if !valid {
+            return Err(format!("无效的日志文件名: {}", name));
+        }
+        
+        let path = self.dir.join(name);
+        if !path.is_file() {
+            return Err(format!("日志文件不存在: {}", name));
+        }
+        Ok(path)
+    }
+    
+    /// Delete a log file by name. The active file is closed first, and the
+    /// next line will start a new one.
+    pub fn delete(&self, name: &str) -> Result<(), String> {
+        let path = self.resolve(name)?;
+        
+        let mut state = self.state.lock();
+        state.current.retain(|_, c| c.path != path);
+        fs::remove_file(&path).map_err(|e| format!("删除日志文件失败: {}", e))
+    }
+    
+    /// Create a new, uniquely named file for the server and current time
+    fn create_file(&self, server_id: &str) -> std::io::Result<CurrentFile> {
+        fs::create_dir_all(&self.dir)?;
+        
+        let short_id: String = server_id
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .take(8)
+            .collect();
+        let stamp = format!("{}-{}", Local::now().format("%Y%m%d-%H%M%S"), short_id);
+        let mut path = self.dir.join(format!("{}{}{}", FILE_PREFIX, stamp, FILE_SUFFIX));
+        let mut n = 2;
+        while path.exists() {
+            path = self
+                .dir
+                .join(format!("{}{}-{}{}", FILE_PREFIX, stamp, n, FILE_SUFFIX));
+            n += 1;
+        }
+        
+        let file = OpenOptions::new().create_new(true).append(true).open(&path)?;
+        Ok(CurrentFile {
+            file,
+            path,
+            size: 0,
+            opened: Instant::now(),
+        })
+    }
+    
+    /// Delete the oldest inactive files so that at most `keep` remain
+    fn prune(&self, keep: usize, current: &HashMap<String, CurrentFile>) {
+        let files = self.files(current);
+        let active = files.iter().filter(|f| f.active).count();
+        let inactive = files.iter().filter(|f| !f.active);
+        for file in inactive.skip(keep.saturating_sub(active)) {
+            let _ = fs::remove_file(&file.path);
+        }
+    }
+    
+    fn log_paths(&self) -> Vec<PathBuf> {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        dir.filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .map(|n| n.to_string_lossy())
+                    .is_some_and(|n| n.starts_with(FILE_PREFIX) && n.ends_with(FILE_SUFFIX))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::LogStream;
+    
+    fn log_files(dir: &tempfile::TempDir, settings: LogFileSettings) -> LogFiles {
+        let log_files = LogFiles::at(dir.path().to_path_buf());
+        log_files.configure(settings);
+        log_files
+    }
+    
+    fn settings() -> LogFileSettings {
+        LogFileSettings {
+            enabled: true,
+            ..LogFileSettings::default()
+        }
+    }
+    
+    fn entry(server_id: &str, line: &str) -> LogEntry {
+        LogEntry {
+            seq: 0,
+            timestamp: 0,
+            server_id: server_id.to_string(),
+            stream: LogStream::Stdout,
+            tag: None,
+            line: line.to_string(),
+        }
+    }
+    
+    #[test]
+    fn rotates_when_file_is_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_files = log_files(
+            &dir,
+            LogFileSettings {
+                max_file_size_kb: 1,
+                ..settings()
+            },
+        );
+        log_files.write(&entry("s1", "first"));
+        assert_eq!(log_files.list().len(), 1);
+        
+        log_files.write(&entry("s1", &"x".repeat(1024)));
+        assert_eq!(log_files.list().len(), 1);
+        log_files.write(&entry("s1", "second file"));
+        let files = log_files.list();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files.iter().filter(|f| f.active).count(), 1);
+    }
+    
+    #[test]
+    fn rotates_when_file_is_too_old() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_files = log_files(&dir, settings());
+        log_files.write(&entry("s1", "first"));
+        log_files.write(&entry("s1", "same file"));
+        assert_eq!(log_files.list().len(), 1);
+        
+        // Any open file is too old for a zero maximum age
+        log_files.configure(LogFileSettings {
+            max_file_age_hours: 0,
+            ..settings()
+        });
+        log_files.write(&entry("s1", "second file"));
+        assert_eq!(log_files.list().len(), 2);
+    }
+    
+    #[test]
+    fn prunes_oldest_but_keeps_active_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_files = log_files(
+            &dir,
+            LogFileSettings {
+                max_files: 2,
+                ..settings()
+            },
+        );
+        log_files.write(&entry("s1", "oldest, still active"));
+        for n in 0..4 {
+            log_files.begin_session("s2");
+            log_files.write(&entry("s2", &format!("session {}", n)));
+        }
+        
+        let files = log_files.list();
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|f| f.active));
+        assert!(files.iter().any(|f| f.name.ends_with("-s1.log")));
+    }
+    
+    #[test]
+    fn resolve_rejects_paths_outside_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_files = log_files(&dir, settings());
+        log_files.write(&entry("s1", "line"));
+        let name = log_files.list()[0].name.clone();
+        assert_eq!(log_files.resolve(&name).unwrap(), dir.path().join(&name));
+        
+        for name in [
+            "../ech-workers-x.log",
+            "ech-workers-../x.log",
+            "ech-workers-..\\x.log",
+            "/etc/ech-workers-x.log",
+            "other.log",
+        ] {
+            let err = log_files.resolve(name).unwrap_err();
+            assert!(err.contains("无效的日志文件名"), "{}: {}", name, err);
+        }
+        assert!(log_files.resolve("ech-workers-missing.log").is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! In-memory log history for ECH Workers
+//! Keeps a bounded ring buffer of worker output so the frontend can
+//! restore and search the log after the window is reopened
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::process::LogStream;
+
+/// Maximum number of lines kept in memory
+const LOG_BUFFER_CAPACITY: usize = 5000;
+
+/// Page size used when a query does not specify one
+const DEFAULT_PAGE_SIZE: usize = 200;
+
+/// A stored log line
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch, taken when the line was received
+    pub timestamp: u64,
+    /// Server whose worker produced the line
+    pub server_id: String,
+    pub stream: LogStream,
+    /// Leading `[...]` tag of the worker line, e.g. `代理` or `ECH`
+    pub tag: Option<String>,
+    pub line: String,
+}
+
+/// Filter and paging options for `get_logs`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LogQuery {
+    /// Only return lines of this server's worker
+    pub server_id: Option<String>,
+    /// Case-insensitive substring to match against the line
+    pub text: Option<String>,
+    /// Tag to match, with or without brackets
+    pub tag: Option<String>,
+    /// Inclusive lower bound on the timestamp, in epoch milliseconds
+    pub since: Option<u64>,
+    /// Inclusive upper bound on the timestamp, in epoch milliseconds
+    pub until: Option<u64>,
+    /// Only return entries older than this sequence number (scroll back)
+    pub before_seq: Option<u64>,
+    /// Only return entries newer than this sequence number (follow)
+    pub after_seq: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+/// One page of query results, oldest entry first
+#[derive(Debug, Clone, Serialize)]
+pub struct LogPage {
+    pub entries: Vec<LogEntry>,
+    /// Whether more matching entries exist beyond this page
+    pub has_more: bool,
+}
+
+/// Bounded, thread-safe ring buffer of log entries
+pub struct LogBuffer {
+    inner: Mutex<LogBufferInner>,
+}
+
+struct LogBufferInner {
+    entries: VecDeque<LogEntry>,
+    next_seq: u64,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(LogBufferInner {
+                entries: VecDeque::with_capacity(LOG_BUFFER_CAPACITY),
+                next_seq: 1,
+            }),
+        }
+    }
+    
+    /// Append a line, evicting the oldest one when full
+    pub fn push(&self, server_id: &str, stream: LogStream, line: String) -> LogEntry {
+        let mut inner = self.inner.lock();
+        let entry = LogEntry {
+            seq: inner.next_seq,
+            timestamp: now_millis(),
+            server_id: server_id.to_string(),
+            stream,
+            tag: parse_tag(&line),
+            line,
+        };
+        inner.next_seq += 1;
+        
+        if inner.entries.len() == LOG_BUFFER_CAPACITY {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back(entry.clone());
+        entry
+    }
+    
+    /// Return the page of entries matching `query`. Without `after_seq`,
+    /// the newest matches are returned.
+    pub fn query(&self, query: &LogQuery) -> LogPage {
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).max(1);
+        let text = query.text.as_deref().map(str::to_lowercase);
+        let tag = query
+            .tag
+            .as_deref()
+            .map(|t| t.trim_start_matches('[').trim_end_matches(']'));
+        
+        let inner = self.inner.lock();
+        let matches = |e: &&LogEntry| {
+            query.server_id.as_ref().is_none_or(|id| &e.server_id == id)
+                && query.before_seq.is_none_or(|s| e.seq < s)
+                && query.after_seq.is_none_or(|s| e.seq > s)
+                && query.since.is_none_or(|t| e.timestamp >= t)
+                && query.until.is_none_or(|t| e.timestamp <= t)
+                && tag.is_none_or(|t| e.tag.as_deref() == Some(t))
+                && text
+                    .as_deref()
+                    .is_none_or(|t| e.line.to_lowercase().contains(t))
+        };
+        
+        let mut entries: Vec<LogEntry>;
+        let has_more;
+        if query.after_seq.is_some() {
+            let mut iter = inner.entries.iter().filter(matches);
+            entries = iter.by_ref().take(limit).cloned().collect();
+            has_more = iter.next().is_some();
+        } else {
+            let mut iter = inner.entries.iter().rev().filter(matches);
+            entries = iter.by_ref().take(limit).cloned().collect();
+            has_more = iter.next().is_some();
+            entries.reverse();
+        }
+        
+        LogPage { entries, has_more }
+    }
+}
+
+/// Extract the first `[...]` tag of a worker line. Go's logger prefixes a
+/// date and time, so the tag is not necessarily at the start.
+fn parse_tag(line: &str) -> Option<String> {
+    let start = line.find('[')?;
+    let len = line[start + 1..].find(']')?;
+    let tag = &line[start + 1..start + 1 + len];
+    (!tag.is_empty()).then(|| tag.to_string())
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn buffer(lines: &[(&str, &str)]) -> LogBuffer {
+        let buffer = LogBuffer::new();
+        for (server_id, line) in lines {
+            buffer.push(server_id, LogStream::Stderr, line.to_string());
+        }
+        buffer
+    }
+    
+    fn seqs(page: &LogPage) -> Vec<u64> {
+        page.entries.iter().map(|e| e.seq).collect()
+    }
+    
+    #[test]
+    fn filters_by_server_tag_and_text() {
+        let buffer = buffer(&[
+            ("a", "2025/01/02 15:04:05 [代理] 服务器启动: 127.0.0.1:30000"),
+            ("b", "2025/01/02 15:04:05 [ECH] 刷新配置..."),
+            ("a", "2025/01/02 15:04:06 [ECH] 配置已加载，长度: 71 字节"),
+            ("a", "panic: runtime error"),
+        ]);
+        
+        let by_server = LogQuery {
+            server_id: Some("a".to_string()),
+            ..LogQuery::default()
+        };
+        assert_eq!(seqs(&buffer.query(&by_server)), [1, 3, 4]);
+        
+        let by_tag = LogQuery {
+            tag: Some("[ECH]".to_string()),
+            ..LogQuery::default()
+        };
+        assert_eq!(seqs(&buffer.query(&by_tag)), [2, 3]);
+        
+        let by_text = LogQuery {
+            server_id: Some("a".to_string()),
+            text: Some("PANIC".to_string()),
+            ..LogQuery::default()
+        };
+        assert_eq!(seqs(&buffer.query(&by_text)), [4]);
+        
+        let in_future = LogQuery {
+            since: Some(now_millis() + 60_000),
+            ..LogQuery::default()
+        };
+        assert!(buffer.query(&in_future).entries.is_empty());
+    }
+    
+    #[test]
+    fn pages_backwards_and_forwards() {
+        let lines: Vec<(&str, &str)> = vec![("a", "line"); 5];
+        let buffer = buffer(&lines);
+        
+        // The newest page first, oldest entry first within it
+        let newest = buffer.query(&LogQuery {
+            limit: Some(2),
+            ..LogQuery::default()
+        });
+        assert_eq!(seqs(&newest), [4, 5]);
+        assert!(newest.has_more);
+        
+        let older = buffer.query(&LogQuery {
+            limit: Some(2),
+            before_seq: Some(4),
+            ..LogQuery::default()
+        });
+        assert_eq!(seqs(&older), [2, 3]);
+        assert!(older.has_more);
+        
+        let oldest = buffer.query(&LogQuery {
+            limit: Some(2),
+            before_seq: Some(2),
+            ..LogQuery::default()
+        });
+        assert_eq!(seqs(&oldest), [1]);
+        assert!(!oldest.has_more);
+        
+        let following = buffer.query(&LogQuery {
+            limit: Some(2),
+            after_seq: Some(1),
+            ..LogQuery::default()
+        });
+        assert_eq!(seqs(&following), [2, 3]);
+        assert!(following.has_more);
+    }
+    
+    #[test]
+    fn evicts_oldest_entries_at_capacity() {
+        let buffer = LogBuffer::new();
+        for i in 0..LOG_BUFFER_CAPACITY + 3 {
+            buffer.push("a", LogStream::Stdout, format!("line {}", i));
+        }
+        
+        let page = buffer.query(&LogQuery {
+            limit: Some(LOG_BUFFER_CAPACITY + 10),
+            ..LogQuery::default()
+        });
+        assert_eq!(page.entries.len(), LOG_BUFFER_CAPACITY);
+        assert!(!page.has_more);
+        assert_eq!(page.entries[0].seq, 4);
+        assert_eq!(page.entries[0].line, "line 3");
+    }
+    
+    #[test]
+    fn extracts_tags_after_the_date() {
+        assert_eq!(parse_tag("2025/01/02 15:04:05 [HTTP-GET] x").as_deref(), Some("HTTP-GET"));
+        assert_eq!(parse_tag("[] empty"), None);
+        assert_eq!(parse_tag("no tag"), None);
+    }
+}
@@ -0,0 +1,424 @@
+//! Keeping ech-workers from outliving the GUI
+//! Workers are tied to the GUI process where the OS allows it, and their
+//! PIDs are recorded so a leftover worker can be cleaned up on next launch
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+
+use crate::config::ConfigManager;
+
+/// A running worker as recorded in the PID file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PidRecord {
+    server_id: String,
+    pid: u32,
+    /// Process start time, used to tell the worker from a process that
+    /// reused its PID
+    start_time: Option<u64>,
+    /// GUI instance that started the worker, with its start time
+    owner_pid: u32,
+    owner_start_time: Option<u64>,
+}
+
+/// PID file listing the workers of every running GUI instance. Each
+/// instance only rewrites its own records.
+pub struct PidFile {
+    path: PathBuf,
+    owner_start_time: Option<u64>,
+    records: Mutex<HashMap<String, PidRecord>>,
+}
+
+impl PidFile {
+    pub fn new() -> Self {
+        Self::at(ConfigManager::get_config_dir().join("workers.pid"))
+    }
+    
+    /// Create a PidFile stored at `path`
+    fn at(path: PathBuf) -> Self {
+        Self {
+            path,
+            owner_start_time: process_info(std::process::id()).map(|p| p.start_time),
+            records: Mutex::new(HashMap::new()),
+        }
+    }
+    
+    /// Record the worker process of a server
+    pub fn add(&self, server_id: &str, pid: u32) {
+        let mut records = self.records.lock();
+        records.insert(
+            server_id.to_string(),
+            PidRecord {
+                server_id: server_id.to_string(),
+                pid,
+                start_time: process_info(pid).map(|p| p.start_time),
+                owner_pid: std::process::id(),
+                owner_start_time: self.owner_start_time,
+            },
+        );
+        self.save(&records);
+    }
+    
+    /// Forget the worker process of a server
+    pub fn remove(&self, server_id: &str) {
+        let mut records = self.records.lock();
+        if records.remove(server_id).is_some() {
+            self.save(&records);
+        }
+    }
+    
+    /// Forget all worker processes
+    pub fn clear(&self) {
+        let mut records = self.records.lock();
+        records.clear();
+        self.save(&records);
+    }
+    
+    /// Kill workers left behind by a GUI instance that is no longer
+    /// running, returning their server ids and PIDs. Only processes that
+    /// still match their record are killed; workers of another running
+    /// instance are left alone.
+    pub fn cleanup_leftovers(&self) -> Vec<(String, u32)> {
+        let records = self.records.lock();
+        let mut killed = Vec::new();
+        let mut kept = Vec::new();
+        for record in self.read() {
+            if self.is_own(&record) {
+                continue;
+            }
+            if is_leftover_worker(&record) {
+                kill_worker_group(record.pid);
+                killed.push((record.server_id, record.pid));
+            } else if owner_alive(&record) {
+                kept.push(record);
+            }
+        }
+        
+        self.write(kept.into_iter().chain(records.values().cloned()).collect());
+        killed
+    }
+    
+    /// Records of all instances in the file
+    fn read(&self) -> Vec<PidRecord> {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+    
+    /// Replace this instance's records in the file
+    fn save(&self, records: &HashMap<String, PidRecord>) {
+        let others = self
+            .read()
+            .into_iter()
+            .filter(|r| !self.is_own(r));
+        self.write(others.chain(records.values().cloned()).collect());
+    }
+    
+    /// Whether a record was written by this instance rather than an
+    /// earlier one that happened to have the same PID
+    fn is_own(&self, record: &PidRecord) -> bool {
+        record.owner_pid == std::process::id() && record.owner_start_time == self.owner_start_time
+    }
+    
+    fn write(&self, records: Vec<PidRecord>) {
+        if records.is_empty() {
+            let _ = fs::remove_file(&self.path);
+            return;
+        }
+        if let Some(parent) = self.path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(content) = serde_json::to_string(&records) {
+            let _ = fs::write(&self.path, content);
+        }
+    }
+}
+
+/// Spawn a worker so that it dies together with the GUI.
+///
+/// `PR_SET_PDEATHSIG` fires when the spawning *thread* exits, not the
+/// process, so every worker is spawned from one long-lived thread.
+#[cfg(target_os = "linux")]
+pub fn spawn(mut cmd: Command) -> io::Result<Child> {
+    use once_cell::sync::Lazy;
+    use std::os::unix::process::CommandExt;
+    use std::sync::mpsc::{self, Sender};
+    use std::thread;
+    
+    type SpawnRequest = (Command, Sender<io::Result<Child>>);
+    
+    static SPAWNER: Lazy<Mutex<Sender<SpawnRequest>>> = Lazy::new(|| {
+        let (tx, rx) = mpsc::channel::<SpawnRequest>();
+        thread::spawn(move || {
+            for (mut cmd, reply) in rx {
+                let _ = reply.send(cmd.spawn());
+            }
+        });
+        Mutex::new(tx)
+    });
+    
+    // Own process group, so signals aimed at the GUI's group don't hit
+    // the worker and stopping can address everything it started
+    cmd.process_group(0);
+    
+    let parent = std::process::id() as libc::pid_t;
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            // The GUI may have died before the signal was armed
+            if libc::getppid() != parent {
+                return Err(io::Error::from_raw_os_error(libc::ESRCH));
+            }
+            Ok(())
+        });
+    }
+    
+    let (tx, rx) = mpsc::channel();
+    SPAWNER
+        .lock()
+        .send((cmd, tx))
+        .map_err(|_| io::Error::other("spawner thread is gone"))?;
+    rx.recv().map_err(|_| io::Error::other("spawner thread is gone"))?
+}
+
+/// Spawn a worker in its own process group where supported
+#[cfg(not(target_os = "linux"))]
+pub fn spawn(mut cmd: Command) -> io::Result<Child> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    cmd.spawn()
+}
+
+/// What the OS reports about a running process
+struct ProcessInfo {
+    /// Start time in a platform-specific unit, fixed for the process's life
+    start_time: u64,
+    parent: u32,
+    /// File name of the executable, if it may be read
+    exe_name: Option<String>,
+}
+
+/// Read `/proc/<pid>/stat` and the `exe` link
+#[cfg(target_os = "linux")]
+fn process_info(pid: u32) -> Option<ProcessInfo> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces, so count fields after it:
+    // ppid is field 4 and starttime field 22
+    let (_, rest) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let exe_name = fs::read_link(format!("/proc/{}/exe", pid))
+        .ok()
+        .and_then(|exe| exe.file_name().map(|n| n.to_string_lossy().to_string()));
+    Some(ProcessInfo {
+        start_time: fields.get(19)?.parse().ok()?,
+        parent: fields.get(1)?.parse().ok()?,
+        exe_name,
+    })
+}
+
+/// Read the BSD info and executable path via `proc_pidinfo`
+#[cfg(target_os = "macos")]
+fn process_info(pid: u32) -> Option<ProcessInfo> {
+    use std::mem;
+    use std::os::raw::{c_int, c_void};
+    use std::path::Path;
+    
+    let pid = pid as c_int;
+    let mut info: libc::proc_bsdinfo = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::proc_bsdinfo>() as c_int;
+    let n = unsafe { libc::proc_pidinfo(pid, libc::PROC_PIDTBSDINFO, 0, &mut info as *mut _ as *mut c_void, size) };
+    if n != size {
+        return None;
+    }
+    
+    let mut path = vec![0u8; libc::PROC_PIDPATHINFO_MAXSIZE as usize];
+    let len = unsafe { libc::proc_pidpath(pid, path.as_mut_ptr() as *mut c_void, path.len() as u32) };
+    let exe_name = (len > 0).then(|| {
+        let path = String::from_utf8_lossy(&path[..len as usize]).to_string();
+        Path::new(&path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(path)
+    });
+    
+    Some(ProcessInfo {
+        start_time: info.pbi_start_tvsec * 1_000_000 + info.pbi_start_tvusec,
+        parent: info.pbi_ppid,
+        exe_name,
+    })
+}
+
+/// Read the process snapshot entry and creation time via the Win32 APIs
+#[cfg(target_os = "windows")]
+fn process_info(pid: u32) -> Option<ProcessInfo> {
+    use std::mem;
+    use winapi::shared::minwindef::{DWORD, FILETIME};
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::processthreadsapi::{GetProcessTimes, OpenProcess};
+    use winapi::um::tlhelp32::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+    };
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+    
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return None;
+        }
+        let mut creation: FILETIME = mem::zeroed();
+        let mut exit: FILETIME = mem::zeroed();
+        let mut kernel: FILETIME = mem::zeroed();
+        let mut user: FILETIME = mem::zeroed();
+        let ok = GetProcessTimes(handle, &mut creation, &mut exit, &mut kernel, &mut user) != 0;
+        CloseHandle(handle);
+        if !ok {
+            return None;
+        }
+        
+        // The parent and executable name come from a process snapshot
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            return None;
+        }
+        let mut entry: PROCESSENTRY32W = mem::zeroed();
+        entry.dwSize = mem::size_of::<PROCESSENTRY32W>() as DWORD;
+        let mut found = None;
+        let mut more = Process32FirstW(snapshot, &mut entry) != 0;
+        while more {
+            if entry.th32ProcessID == pid {
+                let len = entry.szExeFile.iter().position(|&c| c == 0).unwrap_or(entry.szExeFile.len());
+                found = Some((entry.th32ParentProcessID, String::from_utf16_lossy(&entry.szExeFile[..len])));
+                break;
+            }
+            more = Process32NextW(snapshot, &mut entry) != 0;
+        }
+        CloseHandle(snapshot);
+        
+        let (parent, exe_name) = found?;
+        Some(ProcessInfo {
+            start_time: ((creation.dwHighDateTime as u64) << 32) | creation.dwLowDateTime as u64,
+            parent,
+            exe_name: Some(exe_name),
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn process_info(_pid: u32) -> Option<ProcessInfo> {
+    None
+}
+
+/// Whether the recorded process still runs, is the same ech-workers, and
+/// has lost the GUI that started it
+fn is_leftover_worker(record: &PidRecord) -> bool {
+    let Some(info) = process_info(record.pid) else {
+        return false;
+    };
+    let same_worker = record.start_time == Some(info.start_time)
+        && info
+            .exe_name
+            .is_some_and(|name| name.starts_with("ech-workers"));
+    // An orphan is adopted by init on Unix
+    same_worker && (!owner_alive(record) || (cfg!(unix) && info.parent == 1))
+}
+
+/// Whether the GUI instance that recorded a worker still runs. Without a
+/// start time to compare it is assumed to, so its workers are spared.
+fn owner_alive(record: &PidRecord) -> bool {
+    record.owner_start_time.is_none_or(|start_time| {
+        process_info(record.owner_pid).is_some_and(|owner| owner.start_time == start_time)
+    })
+}
+
+/// Terminate a leftover worker's process group, forcing it after a grace period
+#[cfg(unix)]
+fn kill_worker_group(pid: u32) {
+    use std::thread;
+    use std::time::Duration;
+    
+    let pgid = pid as libc::pid_t;
+    unsafe {
+        libc::killpg(pgid, libc::SIGTERM);
+    }
+    for _ in 0..10 {
+        if unsafe { libc::kill(pgid, 0) } != 0 {
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    unsafe {
+        libc::killpg(pgid, libc::SIGKILL);
+    }
+}
+
+/// Terminate a leftover worker; Windows has no graceful signal for it
+#[cfg(windows)]
+fn kill_worker_group(pid: u32) {
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{OpenProcess, TerminateProcess};
+    use winapi::um::winnt::PROCESS_TERMINATE;
+    
+    unsafe {
+        let handle = OpenProcess(PROCESS_TERMINATE, 0, pid);
+        if !handle.is_null() {
+            TerminateProcess(handle, 1);
+            CloseHandle(handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn record(server_id: &str, pid: u32, owner_pid: u32, owner_start_time: Option<u64>) -> PidRecord {
+        PidRecord {
+            server_id: server_id.to_string(),
+            pid,
+            start_time: process_info(pid).map(|p| p.start_time),
+            owner_pid,
+            owner_start_time,
+        }
+    }
+    
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn keeps_records_of_running_instances() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = PidFile::at(dir.path().join("workers.pid"));
+        
+        // Stand-ins for another GUI: init is alive, the other PID is not.
+        // Neither worker is an ech-workers process, so none is killed.
+        let init_start = process_info(1).map(|p| p.start_time);
+        assert!(init_start.is_some());
+        let live = record("live", std::process::id(), 1, init_start);
+        let dead = record("dead", std::process::id(), 1, init_start.map(|t| t + 1));
+        pid_file.write(vec![live, dead]);
+        
+        assert!(pid_file.cleanup_leftovers().is_empty());
+        let servers: Vec<String> = pid_file.read().into_iter().map(|r| r.server_id).collect();
+        assert_eq!(servers, ["live"]);
+        
+        // This instance's records are added next to the other instance's
+        pid_file.add("own", std::process::id());
+        pid_file.remove("own");
+        pid_file.add("own", std::process::id());
+        let mut servers: Vec<String> = pid_file.read().into_iter().map(|r| r.server_id).collect();
+        servers.sort();
+        assert_eq!(servers, ["live", "own"]);
+        
+        pid_file.clear();
+        let servers: Vec<String> = pid_file.read().into_iter().map(|r| r.server_id).collect();
+        assert_eq!(servers, ["live"]);
+    }
+}
@@ -0,0 +1,127 @@
+//! Listen port pre-flight checks for ECH Workers
+//! Verifies that a worker's listen address can be bound before spawning it,
+//! and names the process holding the port where the OS exposes it
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long a readiness probe waits for the worker to accept a connection
+const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A process found listening on a port
+#[derive(Debug, Clone)]
+pub struct PortHolder {
+    pub pid: u32,
+    pub name: String,
+}
+
+/// Check that `listen` can be bound, returning a descriptive error if not
+pub fn check_listen_available(listen: &str) -> Result<(), String> {
+    let addr: SocketAddr = listen
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| format!("无效的监听地址: {}", listen))?;
+    
+    match TcpListener::bind(addr) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            let port = addr.port();
+            Err(match find_port_holder(port) {
+                Some(holder) => format!(
+                    "端口 {} 已被占用 (进程 {} {})",
+                    port, holder.pid, holder.name
+                ),
+                None => format!("端口 {} 已被占用", port),
+            })
+        }
+        Err(e) => Err(format!("无法监听 {}: {}", listen, e)),
+    }
+}
+
+/// Whether something accepts TCP connections on `listen`. Wildcard
+/// addresses are probed through the loopback interface.
+pub fn probe_listening(listen: &str) -> bool {
+    let Some(mut addr) = listen.to_socket_addrs().ok().and_then(|mut a| a.next()) else {
+        return false;
+    };
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).is_ok()
+}
+
+/// Find the process listening on a TCP port
+#[cfg(target_os = "linux")]
+pub fn find_port_holder(port: u16) -> Option<PortHolder> {
+    let inodes: Vec<String> = ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .flat_map(|table| listening_inodes(&table, port))
+        .collect();
+    if inodes.is_empty() {
+        return None;
+    }
+    
+    // Sockets of other users' processes are not readable, so this only
+    // finds holders we have access to
+    for proc_entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = proc_entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(proc_entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(link) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let link = link.to_string_lossy();
+            let Some(inode) = link
+                .strip_prefix("socket:[")
+                .and_then(|s| s.strip_suffix(']'))
+            else {
+                continue;
+            };
+            if inodes.iter().any(|i| i == inode) {
+                let name = std::fs::read_to_string(proc_entry.path().join("comm"))
+                    .map(|s| s.trim().to_string())
+                    .unwrap_or_default();
+                return Some(PortHolder { pid, name });
+            }
+        }
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn find_port_holder(_port: u16) -> Option<PortHolder> {
+    None
+}
+
+/// Socket inodes in a `/proc/net/tcp{,6}` table that listen on `port`
+#[cfg(target_os = "linux")]
+fn listening_inodes(table: &str, port: u16) -> Vec<String> {
+    const TCP_LISTEN: &str = "0A";
+    
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|row| {
+            let fields: Vec<&str> = row.split_whitespace().collect();
+            let local_port = fields.get(1)?.rsplit_once(':')?.1;
+            let state = fields.get(3)?;
+            let inode = fields.get(9)?;
+            let matches = u16::from_str_radix(local_port, 16).ok()? == port && *state == TCP_LISTEN;
+            matches.then(|| inode.to_string())
+        })
+        .collect()
+}
@@ -1,136 +1,598 @@
 //! Process management for ECH Workers
-//! Handles spawning, monitoring, and terminating the ech-workers executable
+//! Handles spawning, monitoring, and terminating the ech-workers executable,
+//! one worker per running server
 
-use parking_lot::Mutex;
-use std::io::{BufRead, BufReader};
+use parking_lot::{Condvar, Mutex};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read};
 use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::thread;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use tauri::{AppHandle, Emitter};
+use tauri_plugin_notification::NotificationExt;
 
 use crate::config::Server;
+use crate::connections::{Connection, ConnectionChange, ConnectionTable};
+use crate::history::HistoryStore;
+use crate::log_files::LogFiles;
+use crate::logs::LogBuffer;
+use crate::orphans::{self, PidFile};
+use crate::port_check;
+use crate::process_stats::{ProcessStats, StatsMonitor};
+use crate::server_fields::ListenAddr;
+use crate::worker_args::{self, WorkerArgs};
+use crate::worker_binary::{WorkerLocator, TOKEN_ENV};
+use crate::supervisor::{CrashLoop, RestartDecision, RestartScheduled, Supervisor};
+use crate::worker_log::{self, WorkerEvent, WorkerEventPayload};
+
+/// Number of trailing log lines attached to an unexpected exit report
+const EXIT_LOG_TAIL: usize = 20;
+
+/// How often the exit watcher polls the child for termination
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How often a start probes the listen socket while waiting for readiness
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often a stop checks whether the worker exited
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a handoff waits for the old worker to release the listen port
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How often a handoff retries binding the listen port
+const HANDOFF_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often resource usage of running workers is sampled
+const STATS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Output stream a worker log line was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+/// A single line of worker output
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    pub stream: LogStream,
+    pub line: String,
+}
+
+/// How a worker was shut down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopOutcome {
+    /// The worker exited on its own within the grace period
+    Graceful,
+    /// The worker had to be killed
+    Forced,
+    /// No worker was running
+    NotRunning,
+}
+
+/// Payload of `process-stopped`
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessStopped {
+    pub server_id: String,
+    pub outcome: StopOutcome,
+}
+
+/// How a server switch replaced the old worker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwitchMode {
+    /// The old server wasn't running, so the new one just started
+    Cold,
+    /// The new worker came up on its own address before the old one stopped
+    Overlap,
+    /// Both share a listen address, so the old worker stopped first
+    Handoff,
+}
+
+/// Payload of `server-switched`
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerSwitched {
+    pub from: String,
+    pub to: String,
+    pub mode: SwitchMode,
+    /// Whether the system proxy is on and points at the new server
+    pub proxy_enabled: bool,
+}
+
+/// Startup lifecycle of a worker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum WorkerState {
+    /// Spawned, no progress reported yet
+    Starting,
+    /// Looking up the ECH config over DoH
+    FetchingEch,
+    /// Accepting proxy connections
+    Listening,
+    /// Exited or gave up before listening
+    Failed { reason: String },
+}
+
+impl WorkerState {
+    /// State a worker enters when it logs `event`, if any
+    fn from_event(event: &WorkerEvent) -> Option<Self> {
+        match event {
+            WorkerEvent::FetchingEch => Some(WorkerState::FetchingEch),
+            WorkerEvent::Listening { .. } => Some(WorkerState::Listening),
+            WorkerEvent::EchFetchFailed { error } => Some(WorkerState::Failed {
+                reason: format!("获取 ECH 配置失败: {}", error),
+            }),
+            WorkerEvent::ListenFailed { error } => Some(WorkerState::Failed {
+                reason: format!("监听失败: {}", error),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Payload of the `process-state` event
+#[derive(Debug, Clone, Serialize)]
+pub struct StateChange {
+    pub server_id: String,
+    #[serde(flatten)]
+    pub state: WorkerState,
+}
+
+/// Payload of lifecycle events that only identify the server
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerEvent {
+    pub server_id: String,
+}
+
+/// Payload of the `process-exited` event, sent when the worker exits on its own
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessExit {
+    pub server_id: String,
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+    pub last_lines: Vec<String>,
+}
+
+impl ProcessExit {
+    fn new(server_id: &str, status: ExitStatus, last_lines: Vec<String>) -> Self {
+        #[cfg(unix)]
+        let signal = {
+            use std::os::unix::process::ExitStatusExt;
+            status.signal()
+        };
+        #[cfg(not(unix))]
+        let signal = None;
+        
+        Self {
+            server_id: server_id.to_string(),
+            code: status.code(),
+            signal,
+            last_lines,
+        }
+    }
+}
+
+/// A server's worker as seen by the frontend
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerInfo {
+    pub server_id: String,
+    pub name: String,
+    pub listen: String,
+    /// Process id, or `None` while waiting to be restarted
+    pub pid: Option<u32>,
+    pub state: WorkerState,
+    /// Executable the current process was started from
+    pub executable: Option<String>,
+}
+
+/// A supervised worker. The entry exists from `start` until `stop`,
+/// including while waiting to be restarted after a crash.
+struct Worker {
+    /// Identifies the spawn the current child belongs to, so watchers of
+    /// an earlier child can tell they are stale
+    session: u64,
+    child: Option<Child>,
+    supervisor: Supervisor,
+    state: WorkerState,
+    /// Whether any session of this worker reached `Listening`. A worker
+    /// that never came up is only restarted if its policy retries startup
+    /// failures; the failed start is reported to the caller either way.
+    was_ready: bool,
+    executable: Option<PathBuf>,
+}
 
 /// Process manager state
 pub struct ProcessManager {
-    child: Mutex<Option<Child>>,
-    is_running: AtomicBool,
+    /// Workers keyed by server id
+    workers: Mutex<HashMap<String, Worker>>,
+    /// Signalled whenever a worker changes state
+    state_changed: Condvar,
+    next_session: AtomicU64,
+    /// History of worker output, kept across restarts
+    logs: LogBuffer,
+    /// On-disk copy of worker output
+    log_files: LogFiles,
+    /// Tunnels currently open through the workers
+    connections: ConnectionTable,
+    /// Completed tunnels
+    history: HistoryStore,
+    /// PIDs of running workers, for cleanup after a crash
+    pid_file: PidFile,
+    /// Resource usage of running workers
+    stats: StatsMonitor,
+    /// Resolves the ech-workers executable
+    locator: WorkerLocator,
 }
 
 impl ProcessManager {
     pub fn new() -> Self {
         Self {
-            child: Mutex::new(None),
-            is_running: AtomicBool::new(false),
+            workers: Mutex::new(HashMap::new()),
+            state_changed: Condvar::new(),
+            next_session: AtomicU64::new(1),
+            logs: LogBuffer::new(),
+            log_files: LogFiles::new(),
+            connections: ConnectionTable::new(),
+            history: HistoryStore::new(),
+            pid_file: PidFile::new(),
+            stats: StatsMonitor::new(),
+            locator: WorkerLocator::new(),
         }
     }
     
-    /// Check if process is running
-    pub fn is_running(&self) -> bool {
-        self.is_running.load(Ordering::SeqCst)
+    /// Worker log history
+    pub fn logs(&self) -> &LogBuffer {
+        &self.logs
     }
     
-    /// Find the ech-workers executable
-    fn find_executable() -> Option<PathBuf> {
-        let exe_name = if cfg!(target_os = "windows") {
-            "ech-workers.exe"
-        } else {
-            "ech-workers"
-        };
-        
-        // Get the directory where the app is located
-        let app_dir = std::env::current_exe()
-            .ok()
-            .and_then(|p| p.parent().map(|p| p.to_path_buf()));
-        
-        // Possible locations to search
-        let mut search_paths = Vec::new();
-        
-        // 1. App bundle directory (for packaged apps)
-        if let Some(dir) = &app_dir {
-            search_paths.push(dir.join(exe_name));
-            // On macOS, check Resources folder
-            if cfg!(target_os = "macos") {
-                search_paths.push(dir.join("../Resources").join(exe_name));
+    /// Locator of the ech-workers executable
+    pub fn locator(&self) -> &WorkerLocator {
+        &self.locator
+    }
+    
+    /// Worker log files
+    pub fn log_files(&self) -> &LogFiles {
+        &self.log_files
+    }
+    
+    /// Live connection table
+    pub fn connections(&self) -> &ConnectionTable {
+        &self.connections
+    }
+    
+    /// Connection history database
+    pub fn history(&self) -> &HistoryStore {
+        &self.history
+    }
+    
+    /// Forget all open connections of a server, e.g. after its worker went away
+    fn clear_connections(&self, server_id: &str, app_handle: &AppHandle) {
+        if self.connections.clear(server_id) {
+            let _ = app_handle.emit(
+                "connections-changed",
+                ConnectionChange::Cleared {
+                    server_id: server_id.to_string(),
+                },
+            );
+        }
+    }
+    
+    /// Add a closed tunnel to the history
+    fn record_history(&self, connection: &Connection, duration_ms: u64, app_handle: &AppHandle) {
+        if let Err(e) = self.history.record(connection, duration_ms) {
+            // Report through the raw log only; parsing it must not recurse
+            let entry = self.logs.push(
+                &connection.server_id,
+                LogStream::Stderr,
+                format!("[系统] {}", e),
+            );
+            let _ = app_handle.emit("log-output", entry);
+        }
+    }
+    
+    /// Move the worker of `session` to a new state and notify waiters
+    fn set_state(&self, server_id: &str, session: u64, state: WorkerState, app_handle: &AppHandle) {
+        {
+            let mut workers = self.workers.lock();
+            let Some(worker) = workers.get_mut(server_id) else {
+                return;
+            };
+            if worker.session != session || worker.state == state {
+                return;
             }
+            // ECH refreshes after startup don't make a listening worker unready
+            if worker.state == WorkerState::Listening && state == WorkerState::FetchingEch {
+                return;
+            }
+            if state == WorkerState::Listening {
+                worker.was_ready = true;
+            }
+            worker.state = state.clone();
         }
+        self.state_changed.notify_all();
         
-        // 2. Parent directory (for development - ech-gui is inside ech-wk)
-        if let Some(dir) = &app_dir {
-            search_paths.push(dir.join("../../..").join(exe_name));
-            search_paths.push(dir.join("../../../..").join(exe_name));
+        let listening = state == WorkerState::Listening;
+        let _ = app_handle.emit(
+            "process-state",
+            StateChange {
+                server_id: server_id.to_string(),
+                state,
+            },
+        );
+        if listening {
+            let _ = app_handle.emit(
+                "process-started",
+                ServerEvent {
+                    server_id: server_id.to_string(),
+                },
+            );
         }
+    }
+    
+    /// Wait until the worker of `session` listens, fails, or `timeout` passes
+    fn wait_ready(
+        &self,
+        server: &Server,
+        session: u64,
+        timeout: Duration,
+        app_handle: &AppHandle,
+    ) -> Result<(), String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            {
+                let mut workers = self.workers.lock();
+                match workers.get(&server.id) {
+                    Some(worker) if worker.session == session => match &worker.state {
+                        WorkerState::Listening => return Ok(()),
+                        WorkerState::Failed { reason } => return Err(reason.clone()),
+                        _ => {}
+                    },
+                    _ => return Err("启动已被取消".to_string()),
+                }
+                self.state_changed.wait_for(&mut workers, READY_POLL_INTERVAL);
+            }
+            
+            // The log line may be missed or delayed, so also probe the socket
+            if port_check::probe_listening(&server.listen) {
+                self.set_state(&server.id, session, WorkerState::Listening, app_handle);
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                let reason = format!("等待服务器就绪超时 ({} 秒)", timeout.as_secs());
+                self.set_state(
+                    &server.id,
+                    session,
+                    WorkerState::Failed {
+                        reason: reason.clone(),
+                    },
+                    app_handle,
+                );
+                return Err(reason);
+            }
+        }
+    }
+    
+    /// Store a log line and forward it to the frontend, together with the
+    /// structured event parsed from it
+    fn push_log(
+        &self,
+        server_id: &str,
+        stream: LogStream,
+        line: String,
+        app_handle: &AppHandle,
+    ) -> Option<WorkerEvent> {
+        let entry = self.logs.push(server_id, stream, line);
+        self.log_files.write(&entry);
+        let event = worker_log::parse_line(&entry.line);
+        let (seq, timestamp) = (entry.seq, entry.timestamp);
+        let _ = app_handle.emit("log-output", entry);
         
-        // 3. Current working directory
-        search_paths.push(PathBuf::from(exe_name));
+        let event = event?;
+        if let Some(change) = self.connections.apply(server_id, &event, timestamp) {
+            if let ConnectionChange::Closed {
+                connection,
+                duration_ms,
+            } = &change
+            {
+                self.record_history(connection, *duration_ms, app_handle);
+            }
+            let _ = app_handle.emit("connections-changed", change);
+        }
+        let _ = app_handle.emit(
+            "worker-event",
+            WorkerEventPayload {
+                server_id: server_id.to_string(),
+                seq,
+                timestamp,
+                event: event.clone(),
+            },
+        );
+        Some(event)
+    }
+    
+    /// Check if the worker of a server is running
+    pub fn is_running(&self, server_id: &str) -> bool {
+        self.workers
+            .lock()
+            .get(server_id)
+            .is_some_and(|w| w.child.is_some())
+    }
+    
+    /// Drop a worker that waits to be restarted, so it doesn't come back
+    /// with settings that changed meanwhile
+    pub fn cancel_restart(&self, server_id: &str) {
+        let mut workers = self.workers.lock();
+        if workers.get(server_id).is_some_and(|w| w.child.is_none()) {
+            workers.remove(server_id);
+        }
+    }
+    
+    /// Servers with a running or restarting worker
+    pub fn workers(&self) -> Vec<WorkerInfo> {
+        let workers = self.workers.lock();
+        let mut list: Vec<WorkerInfo> = workers
+            .iter()
+            .map(|(id, worker)| {
+                let server = worker.supervisor.server();
+                WorkerInfo {
+                    server_id: id.clone(),
+                    name: server.name.clone(),
+                    listen: server.listen.clone(),
+                    pid: worker.child.as_ref().map(|c| c.id()),
+                    state: worker.state.clone(),
+                    executable: worker.executable.as_ref().map(|p| p.display().to_string()),
+                }
+            })
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+    
+    /// Start the ech-workers process for a server and wait until it listens.
+    /// On failure the worker is stopped again, or left to the restart
+    /// policy if it retries startup failures.
+    pub fn start(&'static self, server: &Server, app_handle: AppHandle) -> Result<(), String> {
+        let session = {
+            let mut workers = self.workers.lock();
+            if workers.get(&server.id).is_some_and(|w| w.child.is_some()) {
+                return Err("进程已在运行".to_string());
+            }
+            if let Some((_, other)) = workers.iter().find(|(id, w)| {
+                **id != server.id && listen_conflicts(&w.supervisor.server().listen, &server.listen)
+            }) {
+                return Err(format!(
+                    "监听地址 {} 已被正在运行的服务器 {} 使用",
+                    server.listen,
+                    other.supervisor.server().name
+                ));
+            }
+            
+            // Replacing an entry that waits for a restart cancels that restart
+            let session = self.next_session.fetch_add(1, Ordering::SeqCst);
+            workers.insert(
+                server.id.clone(),
+                Worker {
+                    session,
+                    child: None,
+                    supervisor: Supervisor::new(server.clone()),
+                    state: WorkerState::Starting,
+                    was_ready: false,
+                    executable: None,
+                },
+            );
+            session
+        };
         
-        // 4. Parent of current directory
-        search_paths.push(PathBuf::from("..").join(exe_name));
+        let timeout = Duration::from_secs(server.ready_timeout_secs);
+        let spawned = self.spawn(server, session, app_handle.clone());
+        let result = spawned
+            .clone()
+            .and_then(|()| self.wait_ready(server, session, timeout, &app_handle));
         
-        for path in search_paths {
-            if let Ok(canonical) = path.canonicalize() {
-                if canonical.exists() {
-                    // On Unix, check if executable
-                    #[cfg(unix)]
-                    {
-                        use std::os::unix::fs::PermissionsExt;
-                        if let Ok(meta) = std::fs::metadata(&canonical) {
-                            if meta.permissions().mode() & 0o111 != 0 {
-                                return Some(canonical);
-                            }
+        let policy = &server.restart_policy;
+        if let (Ok(()), Err(reason)) = (&spawned, &result) {
+            if policy.enabled
+                && policy.retry_startup_failures
+                && self.retry_failed_start(server, session, &app_handle)
+            {
+                return Err(format!("{}，将自动重试", reason));
+            }
+        }
+        if result.is_err() {
+            let child_running = {
+                let mut workers = self.workers.lock();
+                match workers.get(&server.id) {
+                    Some(worker) if worker.session == session => {
+                        let running = worker.child.is_some();
+                        if !running {
+                            workers.remove(&server.id);
                         }
+                        running
                     }
-                    // On Windows, just check existence
-                    #[cfg(windows)]
-                    {
-                        return Some(canonical);
-                    }
+                    _ => false,
                 }
+            };
+            if child_running {
+                let _ = tauri::async_runtime::block_on(self.stop(&server.id, &app_handle));
             }
         }
+        result
+    }
+    
+    /// Hand a worker whose start failed over to the restart policy. A child
+    /// that is still running is stopped first; one that already exited is
+    /// restarted by its exit watcher. Returns whether a retry is scheduled,
+    /// which it isn't once the session was stopped or replaced.
+    fn retry_failed_start(&'static self, server: &Server, session: u64, app_handle: &AppHandle) -> bool {
+        let child = match self.workers.lock().get_mut(&server.id) {
+            Some(worker) if worker.session == session => worker.child.take(),
+            _ => return false,
+        };
+        let Some(child) = child else {
+            return true;
+        };
         
-        // 5. Try PATH
-        if let Ok(path) = which::which(exe_name) {
-            return Some(path);
-        }
+        // The exit watcher gives up once the child is taken
+        let grace = Duration::from_millis(server.stop_grace_ms);
+        tauri::async_runtime::block_on(terminate(child, grace));
+        self.pid_file.remove(&server.id);
+        self.clear_connections(&server.id, app_handle);
         
-        None
+        let server_id = server.id.clone();
+        let app_handle = app_handle.clone();
+        thread::spawn(move || self.restart_after_exit(&server_id, session, &app_handle));
+        true
     }
     
-    /// Start the ech-workers process
-    pub fn start(&self, server: &Server, app_handle: AppHandle) -> Result<(), String> {
-        if self.is_running() {
-            return Err("进程已在运行".to_string());
+    /// Spawn a worker for `session`, used by both manual starts and restarts
+    fn spawn(&'static self, server: &Server, session: u64, app_handle: AppHandle) -> Result<(), String> {
+        let binary = self.locator.resolve()?;
+        if let Ok(Some(warning)) = binary.integrity.check() {
+            self.push_log(
+                &server.id,
+                LogStream::Stderr,
+                format!("[警告] {}", warning),
+                &app_handle,
+            );
         }
         
-        let exe_path = Self::find_executable()
-            .ok_or_else(|| "找不到 ech-workers 可执行文件".to_string())?;
+        // The worker only reports a busy port on stderr after starting,
+        // so check it up front for a clear error
+        port_check::check_listen_available(&server.listen)?;
         
         // Build command arguments
-        let mut cmd = Command::new(&exe_path);
+        let mut cmd = Command::new(binary.path());
+        let mut args = WorkerArgs::from_server(server);
+        worker_args::validate_extras(server)?;
+        cmd.envs(&server.env);
         
-        if !server.server.is_empty() {
-            cmd.args(["-f", &server.server]);
-        }
-        if !server.listen.is_empty() {
-            cmd.args(["-l", &server.listen]);
-        }
-        if !server.token.is_empty() {
-            cmd.args(["-token", &server.token]);
-        }
-        if !server.ip.is_empty() {
-            cmd.args(["-ip", &server.ip]);
-        }
-        if !server.dns.is_empty() && server.dns != "dns.alidns.com/dns-query" {
-            cmd.args(["-dns", &server.dns]);
-        }
-        if !server.ech.is_empty() && server.ech != "cloudflare-ech.com" {
-            cmd.args(["-ech", &server.ech]);
-        }
-        if !server.routing_mode.is_empty() {
-            cmd.args(["-routing", &server.routing_mode]);
+        // Pass the token through the environment so it doesn't show up in
+        // the process list; older workers only take it as an argument
+        cmd.env_remove(TOKEN_ENV);
+        if binary.token_env && !args.token.is_empty() {
+            cmd.env(TOKEN_ENV, std::mem::take(&mut args.token));
         }
+        cmd.args(args.to_args());
+        
+        // An older worker dies with a usage dump on unknown flags, so refuse
+        // up front
+        binary.check_flags(args.flag_names())?;
         
         // Configure process
         cmd.stdout(Stdio::piped());
@@ -144,80 +606,509 @@ impl ProcessManager {
             cmd.creation_flags(CREATE_NO_WINDOW);
         }
         
-        let mut child = cmd
-            .spawn()
+        binary.integrity.recheck()?;
+        let mut child = orphans::spawn(cmd)
             .map_err(|e| format!("启动进程失败: {}", e))?;
         
-        self.is_running.store(true, Ordering::SeqCst);
+        // Each worker session gets its own log file
+        let server_id = server.id.clone();
+        self.log_files.begin_session(&server_id);
         
-        // Stream stdout to frontend
+        // Read stdout and stderr concurrently and merge them into one feed.
+        // The Go worker logs to stderr, and an unread pipe can stall it.
+        let (tx, rx) = mpsc::channel::<LogLine>();
         if let Some(stdout) = child.stdout.take() {
-            let app_handle_clone = app_handle.clone();
-            let is_running = Arc::new(AtomicBool::new(true));
-            let is_running_clone = is_running.clone();
-            
-            thread::spawn(move || {
-                let reader = BufReader::new(stdout);
-                for line in reader.lines() {
-                    if !is_running_clone.load(Ordering::SeqCst) {
-                        break;
-                    }
-                    if let Ok(line) = line {
-                        let _ = app_handle_clone.emit("log-output", line);
-                    }
-                }
-            });
+            Self::spawn_reader(stdout, LogStream::Stdout, tx.clone());
         }
+        if let Some(stderr) = child.stderr.take() {
+            Self::spawn_reader(stderr, LogStream::Stderr, tx.clone());
+        }
+        drop(tx);
         
-        // Store child process
-        *self.child.lock() = Some(child);
+        // Forward merged lines to frontend in arrival order, keeping the
+        // most recent ones for the exit report
+        let app_handle_clone = app_handle.clone();
+        let output_server_id = server_id.clone();
+        let token = server.token.clone();
+        let output = thread::spawn(move || {
+            let mut tail = VecDeque::with_capacity(EXIT_LOG_TAIL);
+            for line in rx {
+                let text = redact(line.line, &token);
+                if tail.len() == EXIT_LOG_TAIL {
+                    tail.pop_front();
+                }
+                tail.push_back(text.clone());
+                let event = self.push_log(&output_server_id, line.stream, text, &app_handle_clone);
+                if let Some(state) = event.as_ref().and_then(WorkerState::from_event) {
+                    self.set_state(&output_server_id, session, state, &app_handle_clone);
+                }
+            }
+            Vec::from(tail)
+        });
         
-        // Emit start event
-        let _ = app_handle.emit("process-started", ());
+        // Store child process, unless the user stopped or restarted meanwhile
+        {
+            let mut workers = self.workers.lock();
+            match workers.get_mut(&server_id) {
+                Some(worker) if worker.session == session => {
+                    self.pid_file.add(&server_id, child.id());
+                    worker.child = Some(child);
+                    worker.state = WorkerState::Starting;
+                    worker.executable = Some(binary.path().to_path_buf());
+                }
+                _ => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err("启动已被取消".to_string());
+                }
+            }
+        }
+        self.spawn_exit_watcher(server_id.clone(), session, output, app_handle.clone());
+        self.start_stats_monitor(&app_handle);
+        
+        // `process-started` follows once the worker is listening
+        let _ = app_handle.emit(
+            "process-state",
+            StateChange {
+                server_id,
+                state: WorkerState::Starting,
+            },
+        );
         
         Ok(())
     }
     
-    /// Stop the running process
-    pub fn stop(&self, app_handle: &AppHandle) -> Result<(), String> {
-        self.is_running.store(false, Ordering::SeqCst);
-        
-        let mut child_guard = self.child.lock();
-        if let Some(mut child) = child_guard.take() {
-            // Try graceful termination first
-            #[cfg(unix)]
-            {
-                unsafe {
-                    libc::kill(child.id() as i32, libc::SIGTERM);
+    /// Read lines from a child pipe until EOF and send them to the log feed
+    fn spawn_reader<R: Read + Send + 'static>(pipe: R, stream: LogStream, tx: Sender<LogLine>) {
+        thread::spawn(move || {
+            let mut reader = BufReader::new(pipe);
+            let mut buf = Vec::new();
+            loop {
+                buf.clear();
+                match reader.read_until(b'\n', &mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        let line = String::from_utf8_lossy(&buf)
+                            .trim_end_matches(['\r', '\n'])
+                            .to_string();
+                        if tx.send(LogLine { stream, line }).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+    
+    /// Watch the child of `session` and report when it exits on its own.
+    /// Exits caused by `stop` are not reported here.
+    fn spawn_exit_watcher(
+        &'static self,
+        server_id: String,
+        session: u64,
+        output: JoinHandle<Vec<String>>,
+        app_handle: AppHandle,
+    ) {
+        thread::spawn(move || {
+            let status = loop {
+                thread::sleep(EXIT_POLL_INTERVAL);
+                
+                let mut workers = self.workers.lock();
+                let Some(worker) = workers.get_mut(&server_id) else {
+                    return;
+                };
+                if worker.session != session {
+                    return;
+                }
+                let Some(child) = worker.child.as_mut() else {
+                    return;
+                };
+                if let Ok(Some(status)) = child.try_wait() {
+                    worker.child = None;
+                    self.pid_file.remove(&server_id);
+                    break status;
                 }
+            };
+            
+            // Pipes close once the child is gone, so this collects every
+            // line the worker wrote before exiting
+            let last_lines = output.join().unwrap_or_default();
+            self.clear_connections(&server_id, &app_handle);
+            
+            // Exiting before listening fails the start, unless the log
+            // already gave a more specific reason
+            let starting = self.workers.lock().get(&server_id).is_some_and(|w| {
+                matches!(w.state, WorkerState::Starting | WorkerState::FetchingEch)
+            });
+            if starting {
+                let reason = match status.code() {
+                    Some(code) => format!("进程启动失败，退出码 {}", code),
+                    None => "进程启动失败".to_string(),
+                };
+                self.set_state(&server_id, session, WorkerState::Failed { reason }, &app_handle);
             }
             
-            #[cfg(windows)]
+            let _ = app_handle.emit(
+                "process-exited",
+                ProcessExit::new(&server_id, status, last_lines),
+            );
+            
+            self.restart_after_exit(&server_id, session, &app_handle);
+        });
+    }
+    
+    /// Sample resource usage of all running workers periodically and emit
+    /// it as `process-stats`. Started with the first worker.
+    fn start_stats_monitor(&'static self, app_handle: &AppHandle) {
+        if !self.stats.begin() {
+            return;
+        }
+        
+        let app_handle = app_handle.clone();
+        thread::spawn(move || loop {
+            thread::sleep(STATS_INTERVAL);
+            
+            let pids: Vec<(String, u32)> = self
+                .workers
+                .lock()
+                .iter()
+                .filter_map(|(id, w)| Some((id.clone(), w.child.as_ref()?.id())))
+                .collect();
+            for stats in self.stats.update(&pids) {
+                let _ = app_handle.emit("process-stats", stats);
+            }
+        });
+    }
+    
+    /// Latest resource usage of every running worker
+    pub fn stats(&self) -> Vec<ProcessStats> {
+        self.stats.latest()
+    }
+    
+    /// Apply the restart policy after the child of `session` exited unexpectedly
+    fn restart_after_exit(&'static self, server_id: &str, mut session: u64, app_handle: &AppHandle) {
+        loop {
+            let (decision, server) = {
+                let mut workers = self.workers.lock();
+                let Some(worker) = workers.get_mut(server_id) else {
+                    return;
+                };
+                if worker.session != session {
+                    return;
+                }
+                // Otherwise `start` removes the worker and reports the failure
+                if !worker.was_ready && !worker.supervisor.server().restart_policy.retry_startup_failures {
+                    return;
+                }
+                let decision = worker.supervisor.on_exit(Instant::now());
+                let server = worker.supervisor.server().clone();
+                if !matches!(decision, RestartDecision::Restart { .. }) {
+                    workers.remove(server_id);
+                }
+                (decision, server)
+            };
+            let policy = &server.restart_policy;
+            
+            let delay = match decision {
+                RestartDecision::Disabled => return,
+                RestartDecision::GiveUp { attempts } => {
+                    let _ = app_handle.emit(
+                        "process-crash-loop",
+                        CrashLoop {
+                            server_id: server_id.to_string(),
+                            attempts,
+                            window_secs: policy.window_secs,
+                        },
+                    );
+                    let _ = app_handle
+                        .notification()
+                        .builder()
+                        .title("ECH Workers 已停止")
+                        .body(format!(
+                            "{} 在 {} 秒内连续崩溃 {} 次，已停止自动重启",
+                            server.name, policy.window_secs, attempts
+                        ))
+                        .show();
+                    return;
+                }
+                RestartDecision::Restart { attempt, delay } => {
+                    let _ = app_handle.emit(
+                        "process-restarting",
+                        RestartScheduled {
+                            server_id: server_id.to_string(),
+                            attempt,
+                            max_retries: policy.max_retries,
+                            delay_ms: delay.as_millis() as u64,
+                        },
+                    );
+                    delay
+                }
+            };
+            
+            thread::sleep(delay);
+            
+            // Give up silently if the user started or stopped the worker meanwhile
             {
-                let _ = child.kill();
+                let mut workers = self.workers.lock();
+                match workers.get_mut(server_id) {
+                    Some(worker) if worker.session == session => {
+                        session = self.next_session.fetch_add(1, Ordering::SeqCst);
+                        worker.session = session;
+                    }
+                    _ => return,
+                }
             }
             
-            // Wait a bit, then force kill if needed
-            match child.try_wait() {
-                Ok(Some(_)) => {}
-                _ => {
-                    std::thread::sleep(std::time::Duration::from_millis(500));
-                    let _ = child.kill();
-                    let _ = child.wait();
+            match self.spawn(&server, session, app_handle.clone()) {
+                Ok(()) => return,
+                Err(e) => {
+                    // A failed spawn counts as another crash
+                    self.push_log(
+                        server_id,
+                        LogStream::Stderr,
+                        format!("[系统] 自动重启失败: {}", e),
+                        app_handle,
+                    );
                 }
             }
         }
+    }
+    
+    /// Stop the worker of a server: ask it to exit, wait up to the server's
+    /// grace period, then kill its process group
+    pub async fn stop(&self, server_id: &str, app_handle: &AppHandle) -> Result<StopOutcome, String> {
+        self.stop_within(server_id, None, app_handle).await
+    }
+    
+    /// `stop` with the grace period capped at `max_grace`
+    async fn stop_within(
+        &self,
+        server_id: &str,
+        max_grace: Option<Duration>,
+        app_handle: &AppHandle,
+    ) -> Result<StopOutcome, String> {
+        let worker = self.workers.lock().remove(server_id);
+        
+        let outcome = match worker {
+            Some(Worker {
+                child: Some(child),
+                supervisor,
+                ..
+            }) => {
+                let grace = Duration::from_millis(supervisor.server().stop_grace_ms);
+                let grace = max_grace.map_or(grace, |max| grace.min(max));
+                let outcome = terminate(child, grace).await;
+                self.pid_file.remove(server_id);
+                outcome
+            }
+            _ => StopOutcome::NotRunning,
+        };
+        
+        self.clear_connections(server_id, app_handle);
+        let _ = app_handle.emit(
+            "process-stopped",
+            ProcessStopped {
+                server_id: server_id.to_string(),
+                outcome,
+            },
+        );
+        Ok(outcome)
+    }
+    
+    /// Kill workers a previous, crashed GUI instance left running
+    pub fn cleanup_leftovers(&self, app_handle: &AppHandle) {
+        for (server_id, pid) in self.pid_file.cleanup_leftovers() {
+            self.push_log(
+                &server_id,
+                LogStream::Stderr,
+                format!("[系统] 已清理上次残留的 ech-workers 进程 (PID {})", pid),
+                app_handle,
+            );
+        }
+    }
+    
+    /// Replace the worker of `old` with one for `new`. On different listen
+    /// addresses the new worker is ready before the old one stops; on a
+    /// shared address the old one stops first and the new one retries the
+    /// bind, falling back to `old` if it can't start. Both servers are
+    /// expected in the normalized form `start` takes. `on_ready` runs once
+    /// the new worker listens and before the old one stops.
+    pub async fn switch(
+        &'static self,
+        old: &Server,
+        new: &Server,
+        app_handle: &AppHandle,
+        on_ready: impl FnOnce() + Send,
+    ) -> Result<SwitchMode, String> {
+        if old.id == new.id {
+            return Err("目标服务器与当前服务器相同".to_string());
+        }
+        if self.is_running(&new.id) {
+            return Err(format!("服务器 {} 已在运行", new.name));
+        }
         
-        let _ = app_handle.emit("process-stopped", ());
+        if !self.is_running(&old.id) {
+            self.start_async(new, app_handle).await?;
+            on_ready();
+            return Ok(SwitchMode::Cold);
+        }
+        
+        if !listen_conflicts(&old.listen, &new.listen) {
+            // A failed start leaves the old worker untouched
+            if let Err(e) = self.start_async(new, app_handle).await {
+                self.cancel_restart(&new.id);
+                return Err(e);
+            }
+            on_ready();
+            self.stop(&old.id, app_handle).await?;
+            return Ok(SwitchMode::Overlap);
+        }
+        
+        // The port is unserved until the new worker binds it, so don't wait
+        // out a long grace period
+        self.stop_within(&old.id, Some(HANDOFF_TIMEOUT), app_handle).await?;
+        match self.start_after_release(new, app_handle).await {
+            Ok(()) => {
+                on_ready();
+                Ok(SwitchMode::Handoff)
+            }
+            Err(e) => {
+                // Keep the restart policy from bringing `new` back over `old`
+                self.cancel_restart(&new.id);
+                match self.start_after_release(old, app_handle).await {
+                    Ok(()) => Err(format!("切换失败，已恢复原服务器: {}", e)),
+                    Err(_) => Err(format!("切换失败，原服务器也未能恢复: {}", e)),
+                }
+            }
+        }
+    }
+    
+    /// `start` on a blocking thread, since waiting for readiness takes a while
+    async fn start_async(&'static self, server: &Server, app_handle: &AppHandle) -> Result<(), String> {
+        let server = server.clone();
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn_blocking(move || self.start(&server, app_handle))
+            .await
+            .map_err(|e| format!("启动任务失败: {}", e))?
+    }
+    
+    /// Start once the listen port is free again, for a handoff from a
+    /// worker that just stopped on the same address. A start that fails
+    /// while the port is still taken is retried until `HANDOFF_TIMEOUT`.
+    async fn start_after_release(&'static self, server: &Server, app_handle: &AppHandle) -> Result<(), String> {
+        let deadline = Instant::now() + HANDOFF_TIMEOUT;
+        loop {
+            let released = port_check::check_listen_available(&server.listen).is_ok();
+            if released || Instant::now() >= deadline {
+                match self.start_async(server, app_handle).await {
+                    Err(_) if Instant::now() < deadline
+                        && port_check::check_listen_available(&server.listen).is_err() =>
+                    {
+                        self.cancel_restart(&server.id);
+                    }
+                    result => return result,
+                }
+            }
+            tokio::time::sleep(HANDOFF_POLL_INTERVAL).await;
+        }
+    }
+    
+    /// Stop every worker concurrently
+    pub async fn stop_all(&'static self, app_handle: &AppHandle) -> Result<(), String> {
+        let ids: Vec<String> = self.workers.lock().keys().cloned().collect();
+        let tasks: Vec<_> = ids
+            .into_iter()
+            .map(|id| {
+                let app_handle = app_handle.clone();
+                tauri::async_runtime::spawn(async move { self.stop(&id, &app_handle).await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.map_err(|e| format!("停止进程失败: {}", e))??;
+        }
         Ok(())
     }
 }
 
+/// Terminate a worker, escalating to a kill of its process group once
+/// `grace` passes
+async fn terminate(mut child: Child, grace: Duration) -> StopOutcome {
+    // Windows has no graceful signal for a windowless process
+    #[cfg(windows)]
+    {
+        let _ = grace;
+        let _ = child.kill();
+        let _ = child.wait();
+        StopOutcome::Forced
+    }
+    
+    #[cfg(unix)]
+    {
+        let pid = child.id() as libc::pid_t;
+        unsafe {
+            libc::kill(pid, libc::SIGTERM);
+        }
+        
+        let deadline = Instant::now() + grace;
+        loop {
+            if let Ok(Some(_)) = child.try_wait() {
+                return StopOutcome::Graceful;
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            tokio::time::sleep(STOP_POLL_INTERVAL).await;
+        }
+        
+        // The worker leads its own process group, see `orphans::spawn`
+        unsafe {
+            libc::killpg(pid, libc::SIGKILL);
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+        StopOutcome::Forced
+    }
+}
+
 impl Drop for ProcessManager {
     fn drop(&mut self) {
-        self.is_running.store(false, Ordering::SeqCst);
-        if let Some(mut child) = self.child.lock().take() {
-            let _ = child.kill();
+        for (_, worker) in self.workers.lock().drain() {
+            if let Some(mut child) = worker.child {
+                let _ = child.kill();
+            }
         }
+        self.pid_file.clear();
+    }
+}
+
+/// Mask a secret wherever it appears in a worker log line
+fn redact(line: String, secret: &str) -> String {
+    if secret.is_empty() || !line.contains(secret) {
+        return line;
+    }
+    line.replace(secret, "******")
+}
+
+/// Whether two listen addresses would compete for the same port
+fn listen_conflicts(a: &str, b: &str) -> bool {
+    match (a.parse::<ListenAddr>(), b.parse::<ListenAddr>()) {
+        (Ok(a), Ok(b)) => a.conflicts_with(&b),
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn redacts_every_occurrence() {
+        let line = |s: &str| s.to_string();
+        assert_eq!(redact(line("token=s3cret ok"), "s3cret"), "token=****** ok");
+        assert_eq!(redact(line("[s3cret] s3cret"), "s3cret"), "[******] ******");
+        assert_eq!(redact(line("url?token%3Dsecret"), "secret"), "url?token%3D******");
+        assert_eq!(redact(line("Bearer_secret"), "secret"), "Bearer_******");
+        assert_eq!(redact(line("key=ab;"), "ab"), "key=******;");
+        assert_eq!(redact(line("nothing to hide"), ""), "nothing to hide");
     }
 }
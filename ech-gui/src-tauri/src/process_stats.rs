@@ -0,0 +1,251 @@
+//! Resource usage of running ECH Workers processes
+//! Samples CPU time, memory, threads and open descriptors per worker,
+//! to spot goroutine or socket leaks from the GUI
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::logs::now_millis;
+
+/// Resource usage of a worker at one point in time
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessStats {
+    pub server_id: String,
+    pub pid: u32,
+    /// Sample time in epoch milliseconds
+    pub timestamp: u64,
+    /// Total user and system CPU time in milliseconds
+    pub cpu_time_ms: u64,
+    /// CPU usage since the previous sample, in percent of one core
+    pub cpu_percent: Option<f64>,
+    /// Resident memory in bytes
+    pub memory_bytes: u64,
+    pub threads: u64,
+    /// Open file descriptors, or handles on Windows
+    pub open_fds: u64,
+}
+
+/// Raw counters read from the OS
+struct Sample {
+    cpu_time_ms: u64,
+    memory_bytes: u64,
+    threads: u64,
+    open_fds: u64,
+}
+
+/// Latest stats per server, with CPU usage derived from consecutive samples
+pub struct StatsMonitor {
+    latest: Mutex<HashMap<String, ProcessStats>>,
+    started: AtomicBool,
+}
+
+impl StatsMonitor {
+    pub fn new() -> Self {
+        Self {
+            latest: Mutex::new(HashMap::new()),
+            started: AtomicBool::new(false),
+        }
+    }
+    
+    /// Returns true only on the first call, to start sampling once
+    pub fn begin(&self) -> bool {
+        !self.started.swap(true, Ordering::SeqCst)
+    }
+    
+    /// Latest stats of every sampled worker
+    pub fn latest(&self) -> Vec<ProcessStats> {
+        let mut stats: Vec<ProcessStats> = self.latest.lock().values().cloned().collect();
+        stats.sort_by(|a, b| a.server_id.cmp(&b.server_id));
+        stats
+    }
+    
+    /// Sample the given `(server_id, pid)` workers, forgetting all others
+    pub fn update(&self, workers: &[(String, u32)]) -> Vec<ProcessStats> {
+        let mut latest = self.latest.lock();
+        latest.retain(|id, stats| workers.iter().any(|(w, pid)| w == id && *pid == stats.pid));
+        
+        let mut updated = Vec::new();
+        for (server_id, pid) in workers {
+            let Some(sample) = sample(*pid) else {
+                continue;
+            };
+            let timestamp = now_millis();
+            let cpu_percent = latest.get(server_id).and_then(|prev| {
+                let elapsed = timestamp.checked_sub(prev.timestamp).filter(|&ms| ms > 0)?;
+                let used = sample.cpu_time_ms.saturating_sub(prev.cpu_time_ms);
+                Some(used as f64 * 100.0 / elapsed as f64)
+            });
+            let stats = ProcessStats {
+                server_id: server_id.clone(),
+                pid: *pid,
+                timestamp,
+                cpu_time_ms: sample.cpu_time_ms,
+                cpu_percent,
+                memory_bytes: sample.memory_bytes,
+                threads: sample.threads,
+                open_fds: sample.open_fds,
+            };
+            latest.insert(server_id.clone(), stats.clone());
+            updated.push(stats);
+        }
+        updated
+    }
+}
+
+/// Read the counters of a process from `/proc/<pid>`
+#[cfg(target_os = "linux")]
+fn sample(pid: u32) -> Option<Sample> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // Fields after the command name, starting with the state (field 3)
+    let (_, rest) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let field = |n: usize| -> Option<u64> { fields.get(n - 3)?.parse().ok() };
+    
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+    let cpu_ticks = field(14)? + field(15)?;
+    
+    let open_fds = std::fs::read_dir(format!("/proc/{}/fd", pid))
+        .map(|dir| dir.count() as u64)
+        .unwrap_or(0);
+    
+    Some(Sample {
+        cpu_time_ms: cpu_ticks * 1000 / ticks,
+        memory_bytes: field(24)? * page_size,
+        threads: field(20)?,
+        open_fds,
+    })
+}
+
+/// Read the counters of a process via `proc_pidinfo`
+#[cfg(target_os = "macos")]
+fn sample(pid: u32) -> Option<Sample> {
+    use std::mem;
+    use std::os::raw::{c_int, c_void};
+    
+    let pid = pid as c_int;
+    let mut info: libc::proc_taskinfo = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::proc_taskinfo>() as c_int;
+    let n = unsafe {
+        libc::proc_pidinfo(
+            pid,
+            libc::PROC_PIDTASKINFO,
+            0,
+            &mut info as *mut _ as *mut c_void,
+            size,
+        )
+    };
+    if n != size {
+        return None;
+    }
+    
+    // Task CPU times are in Mach absolute time units
+    #[allow(deprecated)]
+    let timebase = unsafe {
+        let mut timebase: libc::mach_timebase_info = mem::zeroed();
+        libc::mach_timebase_info(&mut timebase);
+        timebase
+    };
+    let cpu_ticks = (info.pti_total_user + info.pti_total_system) as u128;
+    let cpu_ns = cpu_ticks * timebase.numer.max(1) as u128 / timebase.denom.max(1) as u128;
+    
+    // A first call sizes the buffer, the second lists the descriptors
+    let fd_size = mem::size_of::<libc::proc_fdinfo>();
+    let estimate = unsafe { libc::proc_pidinfo(pid, libc::PROC_PIDLISTFDS, 0, std::ptr::null_mut(), 0) };
+    let open_fds = if estimate > 0 {
+        let mut fds: Vec<libc::proc_fdinfo> = Vec::with_capacity(estimate as usize / fd_size);
+        let bytes = unsafe {
+            libc::proc_pidinfo(
+                pid,
+                libc::PROC_PIDLISTFDS,
+                0,
+                fds.as_mut_ptr() as *mut c_void,
+                (fds.capacity() * fd_size) as c_int,
+            )
+        };
+        bytes.max(0) as u64 / fd_size as u64
+    } else {
+        0
+    };
+    
+    Some(Sample {
+        cpu_time_ms: (cpu_ns / 1_000_000) as u64,
+        memory_bytes: info.pti_resident_size,
+        threads: info.pti_threadnum.max(0) as u64,
+        open_fds,
+    })
+}
+
+/// Read the counters of a process via the Win32 process APIs
+#[cfg(target_os = "windows")]
+fn sample(pid: u32) -> Option<Sample> {
+    use std::mem;
+    use winapi::shared::minwindef::{DWORD, FILETIME};
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::processthreadsapi::{GetProcessHandleCount, GetProcessTimes, OpenProcess};
+    use winapi::um::psapi::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+    use winapi::um::tlhelp32::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+    };
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+    
+    let filetime = |t: FILETIME| ((t.dwHighDateTime as u64) << 32) | t.dwLowDateTime as u64;
+    
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return None;
+        }
+        
+        let mut creation: FILETIME = mem::zeroed();
+        let mut exit: FILETIME = mem::zeroed();
+        let mut kernel: FILETIME = mem::zeroed();
+        let mut user: FILETIME = mem::zeroed();
+        let mut memory: PROCESS_MEMORY_COUNTERS = mem::zeroed();
+        let mut handles: DWORD = 0;
+        
+        let ok = GetProcessTimes(handle, &mut creation, &mut exit, &mut kernel, &mut user) != 0
+            && GetProcessMemoryInfo(
+                handle,
+                &mut memory,
+                mem::size_of::<PROCESS_MEMORY_COUNTERS>() as DWORD,
+            ) != 0;
+        GetProcessHandleCount(handle, &mut handles);
+        CloseHandle(handle);
+        if !ok {
+            return None;
+        }
+        
+        // The thread count is only available from a process snapshot
+        let mut threads = 0;
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot != INVALID_HANDLE_VALUE {
+            let mut entry: PROCESSENTRY32W = mem::zeroed();
+            entry.dwSize = mem::size_of::<PROCESSENTRY32W>() as DWORD;
+            let mut more = Process32FirstW(snapshot, &mut entry) != 0;
+            while more {
+                if entry.th32ProcessID == pid {
+                    threads = entry.cntThreads as u64;
+                    break;
+                }
+                more = Process32NextW(snapshot, &mut entry) != 0;
+            }
+            CloseHandle(snapshot);
+        }
+        
+        // FILETIME counts 100 ns intervals
+        Some(Sample {
+            cpu_time_ms: (filetime(kernel) + filetime(user)) / 10_000,
+            memory_bytes: memory.WorkingSetSize as u64,
+            threads,
+            open_fds: handles as u64,
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn sample(_pid: u32) -> Option<Sample> {
+    None
+}
@@ -0,0 +1,452 @@
+//! Validated server fields
+//! Parses the free-form strings of a `Server` into typed values, so typos
+//! are reported per field before the worker is started with them
+
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+use crate::config::{RoutingMode, Server};
+
+/// A problem with one field, named as in `Server`
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    /// Form label of the field
+    pub fn label(&self) -> &'static str {
+        match self.field {
+            "server" => "服务地址",
+            "listen" => "监听地址",
+            "ip" => "优选 IP/域名",
+            "dns" => "DOH 服务器",
+            "ech" => "ECH 域名",
+            "routing_mode" => "分流模式",
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.label(), self.message)
+    }
+}
+
+/// An IP address or DNS name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    Ip(IpAddr),
+    Name(String),
+}
+
+impl FromStr for Host {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, String> {
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(Host::Ip(ip));
+        }
+        let name = s.strip_suffix('.').unwrap_or(s);
+        let labels: Vec<&str> = name.split('.').collect();
+        if labels.iter().all(|l| !l.is_empty() && l.chars().all(|c| c.is_ascii_digit())) {
+            return Err(format!("{} 不是有效的 IP 地址", s));
+        }
+        let valid_label = |l: &&str| {
+            (1..=63).contains(&l.len())
+                && !l.starts_with('-')
+                && !l.ends_with('-')
+                && l.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        };
+        if name.is_empty() || name.len() > 253 || !labels.iter().all(valid_label) {
+            return Err(format!("{} 不是有效的 IP 地址或域名", s));
+        }
+        Ok(Host::Name(name.to_string()))
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Host::Ip(ip) => write!(f, "{}", ip),
+            Host::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Local address the worker's proxy listens on, such as `127.0.0.1:30000`
+/// or `[::1]:30000`. `localhost` stands for `127.0.0.1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenAddr(SocketAddr);
+
+impl FromStr for ListenAddr {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("不能为空".to_string());
+        }
+        let (host, port) = split_host_port(s)?;
+        let ip = match host {
+            "" => return Err("缺少 IP 地址，如 127.0.0.1:30000".to_string()),
+            "localhost" => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            _ => host
+                .parse()
+                .map_err(|_| format!("{} 不是 IP 地址，如 127.0.0.1 或 [::1]", host))?,
+        };
+        Ok(Self(SocketAddr::new(ip, port)))
+    }
+}
+
+impl ListenAddr {
+    /// Whether both would bind the same port, counting wildcard addresses
+    /// as overlapping every other address
+    pub fn conflicts_with(&self, other: &ListenAddr) -> bool {
+        self.0.port() == other.0.port()
+            && (self.0.ip() == other.0.ip() || self.0.ip().is_unspecified() || other.0.ip().is_unspecified())
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Address of the Workers endpoint, `host:port[/path]` as accepted by the
+/// worker's `parseServerAddr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerEndpoint {
+    pub host: Host,
+    pub port: u16,
+    /// WebSocket path, `/` when not given
+    pub path: String,
+}
+
+impl FromStr for WorkerEndpoint {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("不能为空".to_string());
+        }
+        if s.contains("://") {
+            return Err("不需要 wss:// 等协议前缀，格式为 主机:端口[/路径]".to_string());
+        }
+        let (addr, path) = match s.find('/') {
+            Some(i) => (&s[..i], &s[i..]),
+            None => (s, "/"),
+        };
+        let (host, port) = split_host_port(addr)?;
+        if host.is_empty() {
+            return Err("缺少主机名，格式为 主机:端口[/路径]".to_string());
+        }
+        Ok(Self {
+            host: host.parse()?,
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for WorkerEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.host {
+            Host::Ip(IpAddr::V6(ip)) => write!(f, "[{}]:{}", ip, self.port)?,
+            host => write!(f, "{}:{}", host, self.port)?,
+        }
+        if self.path != "/" {
+            write!(f, "{}", self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// DNS-over-HTTPS server used to look up the ECH config. The scheme is
+/// optional and defaults to `https://`, as in the worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DohUrl(String);
+
+impl FromStr for DohUrl {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let rest = match s.split_once("://") {
+            Some(("https" | "http", rest)) => rest,
+            Some((scheme, _)) => return Err(format!("不支持的协议 {}://，应为 https://", scheme)),
+            None => s,
+        };
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err("缺少主机名，如 dns.alidns.com/dns-query".to_string());
+        }
+        let host = if authority.ends_with(']') || !authority.contains(':') {
+            authority.trim_start_matches('[').trim_end_matches(']')
+        } else {
+            split_host_port(authority)?.0
+        };
+        host.parse::<Host>()?;
+        if path.len() <= 1 {
+            return Err("缺少查询路径，如 dns.alidns.com/dns-query".to_string());
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl fmt::Display for DohUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Domain whose HTTPS record carries the ECH config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EchDomain(String);
+
+impl FromStr for EchDomain {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim().parse()? {
+            Host::Name(name) => Ok(Self(name)),
+            Host::Ip(_) => Err("应为域名，不能是 IP 地址".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for EchDomain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A server whose fields all parsed. Empty optional fields are left to
+/// the worker's defaults.
+#[derive(Debug, Clone)]
+pub struct ValidatedServer {
+    pub listen: ListenAddr,
+    pub endpoint: WorkerEndpoint,
+    /// Address to connect to instead of resolving the endpoint's host
+    pub ip: Option<Host>,
+    pub dns: Option<DohUrl>,
+    pub ech: Option<EchDomain>,
+}
+
+impl ValidatedServer {
+    /// Parse the fields of a server, collecting an error for each bad one
+    pub fn validate(server: &Server) -> Result<Self, Vec<FieldError>> {
+        let mut errors = Vec::new();
+        let endpoint = check(&mut errors, "server", server.server.parse());
+        let listen = check(&mut errors, "listen", server.listen.parse());
+        let ip = check(&mut errors, "ip", optional(&server.ip, |s| {
+            s.parse().map_err(|_| format!("{} 不是有效的 IP 地址或域名，且不能带端口", s))
+        }));
+        let dns = check(&mut errors, "dns", optional(&server.dns, str::parse));
+        let ech = check(&mut errors, "ech", optional(&server.ech, str::parse));
+        
+        match (endpoint, listen, ip, dns, ech) {
+            (Some(endpoint), Some(listen), Some(ip), Some(dns), Some(ech)) => {
+                Ok(Self { listen, endpoint, ip, dns, ech })
+            }
+            _ => Err(errors),
+        }
+    }
+    
+    /// `server` with its fields in canonical form
+    pub fn apply(&self, server: &Server) -> Server {
+        let text = |value: Option<String>| value.unwrap_or_default();
+        Server {
+            server: self.endpoint.to_string(),
+            listen: self.listen.to_string(),
+            ip: text(self.ip.as_ref().map(Host::to_string)),
+            dns: text(self.dns.as_ref().map(DohUrl::to_string)),
+            ech: text(self.ech.as_ref().map(EchDomain::to_string)),
+            ..server.clone()
+        }
+    }
+}
+
+/// Check a server as sent by the frontend, returning an error per invalid
+/// field. Unlike in the config file, an unknown routing mode isn't replaced
+/// but reported as a `routing_mode` error.
+pub fn validate_input(mut input: Value) -> Result<Vec<FieldError>, String> {
+    let mut errors = Vec::new();
+    if let Some(mode) = input.get("routing_mode") {
+        let parsed = match mode.as_str() {
+            Some(mode) => mode.parse::<RoutingMode>(),
+            None => Err(format!("无效的分流模式 {}", mode)),
+        };
+        // Check the other fields without it
+        if check(&mut errors, "routing_mode", parsed).is_none() {
+            if let Some(server) = input.as_object_mut() {
+                server.remove("routing_mode");
+            }
+        }
+    }
+    
+    let server: Server = serde_json::from_value(input).map_err(|e| format!("服务器配置格式错误: {}", e))?;
+    errors.extend(ValidatedServer::validate(&server).err().unwrap_or_default());
+    Ok(errors)
+}
+
+/// Record the error of a field, if any
+fn check<T>(errors: &mut Vec<FieldError>, field: &'static str, result: Result<T, String>) -> Option<T> {
+    result
+        .map_err(|message| errors.push(FieldError { field, message }))
+        .ok()
+}
+
+/// Parse a field that may be left empty
+fn optional<T>(value: &str, parse: impl FnOnce(&str) -> Result<T, String>) -> Result<Option<T>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        parse(value).map(Some)
+    }
+}
+
+/// Split `host:port` like Go's `net.SplitHostPort`, with IPv6 hosts in
+/// brackets, and check the port is a valid number
+fn split_host_port(addr: &str) -> Result<(&str, u16), String> {
+    let (host, port) = if let Some(rest) = addr.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("{} 缺少 ]", addr))?;
+        let port = rest
+            .strip_prefix(':')
+            .ok_or_else(|| format!("{} 缺少端口", addr))?;
+        (host, port)
+    } else {
+        let (host, port) = addr
+            .rsplit_once(':')
+            .ok_or_else(|| format!("{} 缺少端口，格式为 主机:端口", addr))?;
+        if host.contains(':') {
+            return Err(format!("IPv6 地址需写在方括号中，如 [{}]:{}", host, port));
+        }
+        (host, port)
+    };
+    match port.parse::<u16>() {
+        Ok(port) if port != 0 => Ok((host, port)),
+        _ => Err(format!("端口 {} 无效，应为 1-65535", port)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+    
+    #[test]
+    fn parses_listen_addresses() {
+        let addr: ListenAddr = "127.0.0.1:30000".parse().unwrap();
+        assert_eq!(addr.to_string(), "127.0.0.1:30000");
+        let addr: ListenAddr = " [::1]:1080 ".parse().unwrap();
+        assert_eq!(addr.0, SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 1080));
+        let addr: ListenAddr = "localhost:30000".parse().unwrap();
+        assert_eq!(addr.to_string(), "127.0.0.1:30000");
+        assert!(addr.conflicts_with(&"0.0.0.0:30000".parse().unwrap()));
+        assert!(!addr.conflicts_with(&"127.0.0.2:30000".parse().unwrap()));
+        
+        for bad in ["", "127.0.0.1", "127.0.0.1:99999", "127.0.0.1:0", ":30000", "::1:30000", "example.com:80"] {
+            assert!(bad.parse::<ListenAddr>().is_err(), "{}", bad);
+        }
+    }
+    
+    #[test]
+    fn parses_worker_endpoints() {
+        let endpoint: WorkerEndpoint = "a.workers.dev:443".parse().unwrap();
+        assert_eq!(endpoint.host, Host::Name("a.workers.dev".to_string()));
+        assert_eq!(endpoint.port, 443);
+        assert_eq!(endpoint.path, "/");
+        
+        let endpoint: WorkerEndpoint = "[2606:4700::1]:8443/ws?ed=2048".parse().unwrap();
+        assert_eq!(endpoint.path, "/ws?ed=2048");
+        assert_eq!(endpoint.to_string(), "[2606:4700::1]:8443/ws?ed=2048");
+        
+        for bad in ["example.com443", "example.com:99999", ":443", "wss://a.dev:443", "a..dev:443", "1.2.3.400:443"] {
+            assert!(bad.parse::<WorkerEndpoint>().is_err(), "{}", bad);
+        }
+    }
+    
+    #[test]
+    fn parses_doh_urls() {
+        for good in [
+            "dns.alidns.com/dns-query",
+            "https://doh.pub/dns-query",
+            "http://127.0.0.1:8053/dns-query",
+            "https://[2606:4700:4700::1111]/dns-query",
+        ] {
+            assert!(good.parse::<DohUrl>().is_ok(), "{}", good);
+        }
+        for bad in ["dns.alidns.com", "tls://1.1.1.1/dns-query", "/dns-query", "doh.pub:0/dns-query"] {
+            assert!(bad.parse::<DohUrl>().is_err(), "{}", bad);
+        }
+    }
+    
+    #[test]
+    fn parses_ech_domains() {
+        let domain: EchDomain = "cloudflare-ech.com.".parse().unwrap();
+        assert_eq!(domain.to_string(), "cloudflare-ech.com");
+        for bad in ["1.1.1.1", "cloudflare-ech.com:443", "-bad.com", "a b.com"] {
+            assert!(bad.parse::<EchDomain>().is_err(), "{}", bad);
+        }
+    }
+    
+    #[test]
+    fn reports_each_bad_field() {
+        let server = Server {
+            server: "example.com443".to_string(),
+            listen: "127.0.0.1:99999".to_string(),
+            ip: "1.2.3.4:443".to_string(),
+            ech: "1.1.1.1".to_string(),
+            ..Server::default()
+        };
+        let errors = ValidatedServer::validate(&server).unwrap_err();
+        let fields: Vec<&str> = errors.iter().map(|e| e.field).collect();
+        assert_eq!(fields, ["server", "listen", "ip", "ech"]);
+    }
+    
+    #[test]
+    fn reports_unknown_routing_mode_of_input() {
+        let mut input = serde_json::to_value(Server {
+            listen: "127.0.0.1:99999".to_string(),
+            ..Server::default()
+        })
+        .unwrap();
+        input["routing_mode"] = Value::from("Bypass_CN");
+        let fields: Vec<&str> = validate_input(input).unwrap().iter().map(|e| e.field).collect();
+        assert_eq!(fields, ["routing_mode", "listen"]);
+        
+        let input = serde_json::to_value(Server::default()).unwrap();
+        assert!(validate_input(input).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn normalizes_valid_servers() {
+        let server = Server {
+            server: " [2606:4700:0::1]:443/ ".to_string(),
+            listen: "localhost:30000".to_string(),
+            ip: String::new(),
+            dns: String::new(),
+            ..Server::default()
+        };
+        let applied = ValidatedServer::validate(&server).unwrap().apply(&server);
+        assert_eq!(applied.server, "[2606:4700::1]:443");
+        assert_eq!(applied.listen, "127.0.0.1:30000");
+        assert_eq!(applied.ip, "");
+        assert_eq!(applied.dns, "");
+        assert_eq!(applied.ech, server.ech);
+    }
+}
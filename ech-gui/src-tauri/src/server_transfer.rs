@@ -0,0 +1,302 @@
+//! Sharing server lists as files
+//! Exports selected servers as a versioned JSON document, and previews the
+//! import of one against the existing servers before applying it
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::config::{ConfigManager, Server};
+use crate::config_migrations::{self, CURRENT_SCHEMA_VERSION};
+use crate::server_fields::{FieldError, ValidatedServer, WorkerEndpoint};
+use crate::worker_args;
+
+/// Marks a JSON document as a server export
+const EXPORT_FORMAT: &str = "ech-workers-servers";
+
+/// A document of exported servers. The servers follow the config schema of
+/// `schema_version` and are upgraded on import like the config file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerExport {
+    pub format: String,
+    pub schema_version: u32,
+    /// Export time in RFC 3339
+    pub exported_at: String,
+    /// Whether the servers carry their tokens
+    pub includes_token: bool,
+    pub servers: Vec<Server>,
+}
+
+impl ServerExport {
+    pub fn new(servers: Vec<Server>, include_token: bool) -> Self {
+        let servers = if include_token {
+            servers
+        } else {
+            servers
+                .into_iter()
+                .map(|server| Server {
+                    token: String::new(),
+                    ..server
+                })
+                .collect()
+        };
+        Self {
+            format: EXPORT_FORMAT.to_string(),
+            schema_version: CURRENT_SCHEMA_VERSION,
+            exported_at: Local::now().to_rfc3339(),
+            includes_token: include_token,
+            servers,
+        }
+    }
+    
+    /// Parse an export document, upgrading servers from older versions
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut value: Value =
+            serde_json::from_str(content).map_err(|e| format!("导入文件格式错误: {}", e))?;
+        if value.get("format").and_then(Value::as_str) != Some(EXPORT_FORMAT) {
+            return Err("不是服务器导出文件".to_string());
+        }
+        config_migrations::migrate(&mut value)?;
+        serde_json::from_value(value).map_err(|e| format!("导入文件格式错误: {}", e))
+    }
+    
+    /// Write the document to the downloads folder, returning its path
+    pub fn save(&self) -> Result<PathBuf, String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| format!("序列化服务器失败: {}", e))?;
+        let dir = dirs::download_dir()
+            .or_else(dirs::home_dir)
+            .unwrap_or_else(ConfigManager::get_config_dir);
+        let name = format!("ech-servers-{}.json", Local::now().format("%Y%m%d-%H%M%S"));
+        let path = dir.join(name);
+        fs::write(&path, json).map_err(|e| format!("写入导出文件失败: {}", e))?;
+        Ok(path)
+    }
+}
+
+/// How imported servers are combined with the existing ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Add them to the existing servers
+    Merge,
+    /// Replace all existing servers
+    Replace,
+}
+
+/// An imported server and what importing it would do
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportCandidate {
+    pub server: Server,
+    /// Name of a server with the same endpoint and token; duplicates are
+    /// skipped
+    pub duplicate_of: Option<String>,
+    /// Whether the id was already taken and has been regenerated
+    pub id_changed: bool,
+    /// Fields to fix before the server can be started
+    pub errors: Vec<FieldError>,
+    /// Extra worker flags and environment variables the file carries, as
+    /// `-name=value` and `NAME=value`. They can run arbitrary code, so
+    /// they are only imported when the user opts in.
+    pub extras: Vec<String>,
+    /// Whether `extras` are kept on the imported server
+    pub extras_kept: bool,
+}
+
+/// The outcome of an import, shown to the user before it is applied
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportPreview {
+    pub exported_at: String,
+    pub includes_token: bool,
+    pub mode: ImportMode,
+    /// Whether extra args and environment variables are imported
+    pub keep_extras: bool,
+    pub servers: Vec<ImportCandidate>,
+}
+
+impl ImportPreview {
+    /// Work out how `export` would be imported next to `existing`. Extra
+    /// args and environment variables are stripped unless `keep_extras`.
+    pub fn new(
+        export: ServerExport,
+        existing: &[Server],
+        mode: ImportMode,
+        keep_extras: bool,
+    ) -> Result<Self, String> {
+        // Servers present after the import so far
+        let mut kept: Vec<Server> = match mode {
+            ImportMode::Merge => existing.to_vec(),
+            ImportMode::Replace => Vec::new(),
+        };
+        let mut ids: HashSet<String> = kept.iter().map(|s| s.id.clone()).collect();
+        
+        let mut servers = Vec::new();
+        for mut server in export.servers {
+            worker_args::validate_extras(&server).map_err(|e| format!("{}: {}", server.name, e))?;
+            
+            let extras: Vec<String> = server
+                .extra_args
+                .iter()
+                .map(|(name, value)| format!("-{}={}", name, value))
+                .chain(server.env.iter().map(|(name, value)| format!("{}={}", name, value)))
+                .collect();
+            if !keep_extras {
+                server.extra_args.clear();
+                server.env.clear();
+            }
+            
+            let duplicate_of = kept
+                .iter()
+                .find(|s| is_duplicate(s, &server, export.includes_token))
+                .map(|s| s.name.clone());
+            let mut id_changed = false;
+            if duplicate_of.is_none() {
+                if server.id.is_empty() || ids.contains(&server.id) {
+                    server.id = Uuid::new_v4().to_string();
+                    id_changed = true;
+                }
+                ids.insert(server.id.clone());
+                kept.push(server.clone());
+            }
+            
+            servers.push(ImportCandidate {
+                errors: ValidatedServer::validate(&server).err().unwrap_or_default(),
+                server,
+                duplicate_of,
+                id_changed,
+                extras_kept: keep_extras && !extras.is_empty(),
+                extras,
+            });
+        }
+        
+        Ok(Self {
+            exported_at: export.exported_at,
+            includes_token: export.includes_token,
+            mode,
+            keep_extras,
+            servers,
+        })
+    }
+    
+    /// The servers to import, without duplicates
+    pub fn into_servers(self) -> Vec<Server> {
+        self.servers
+            .into_iter()
+            .filter(|c| c.duplicate_of.is_none())
+            .map(|c| c.server)
+            .collect()
+    }
+}
+
+/// Whether two servers reach the same endpoint with the same token. Tokens
+/// are only compared if the import carries them.
+fn is_duplicate(existing: &Server, imported: &Server, compare_token: bool) -> bool {
+    endpoint_key(&existing.server) == endpoint_key(&imported.server)
+        && (!compare_token || existing.token == imported.token)
+}
+
+/// Endpoint in a form where equivalent spellings compare equal
+fn endpoint_key(server: &str) -> String {
+    match server.parse::<WorkerEndpoint>() {
+        Ok(endpoint) => format!(
+            "{}:{}{}",
+            endpoint.host.to_string().to_ascii_lowercase(),
+            endpoint.port,
+            endpoint.path
+        ),
+        Err(_) => server.trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn server(id: &str, endpoint: &str, token: &str) -> Server {
+        Server {
+            id: id.to_string(),
+            name: id.to_string(),
+            server: endpoint.to_string(),
+            token: token.to_string(),
+            ..Server::default()
+        }
+    }
+    
+    #[test]
+    fn round_trips_without_tokens() {
+        let export = ServerExport::new(vec![server("a", "a.dev:443", "secret")], false);
+        let json = serde_json::to_string(&export).unwrap();
+        let parsed = ServerExport::parse(&json).unwrap();
+        assert_eq!(parsed.schema_version, CURRENT_SCHEMA_VERSION);
+        assert!(!parsed.includes_token);
+        assert_eq!(parsed.servers[0].server, "a.dev:443");
+        assert_eq!(parsed.servers[0].token, "");
+        
+        assert!(ServerExport::parse(r#"{"servers": []}"#).is_err());
+    }
+    
+    #[test]
+    fn merge_skips_duplicates_and_renews_taken_ids() {
+        let existing = [server("a", "A.dev:443/", "t1")];
+        let export = ServerExport::new(
+            vec![
+                server("a", "a.dev:443", "t1"),
+                server("a", "a.dev:443", "t2"),
+                server("b", "b.dev:443", ""),
+                server("c", "b.dev:443", ""),
+            ],
+            true,
+        );
+        let preview = ImportPreview::new(export, &existing, ImportMode::Merge, false).unwrap();
+        
+        let duplicates: Vec<Option<&str>> = preview.servers.iter().map(|c| c.duplicate_of.as_deref()).collect();
+        assert_eq!(duplicates, [Some("a"), None, None, Some("b")]);
+        assert!(preview.servers[1].id_changed);
+        assert!(!preview.servers[2].id_changed);
+        
+        let imported = preview.into_servers();
+        assert_eq!(imported.len(), 2);
+        assert_ne!(imported[0].id, "a");
+        assert_eq!(imported[1].id, "b");
+    }
+    
+    #[test]
+    fn replace_ignores_existing_servers() {
+        let existing = [server("a", "a.dev:443", "")];
+        let export = ServerExport::new(vec![server("a", "a.dev:443", "")], false);
+        let preview = ImportPreview::new(export, &existing, ImportMode::Replace, false).unwrap();
+        assert!(preview.servers[0].duplicate_of.is_none());
+        assert!(!preview.servers[0].id_changed);
+    }
+    
+    #[test]
+    fn rejects_managed_extra_args() {
+        let mut bad = server("a", "a.dev:443", "");
+        bad.extra_args.insert("token".to_string(), "x".to_string());
+        let export = ServerExport::new(vec![bad], true);
+        assert!(ImportPreview::new(export, &[], ImportMode::Merge, true).is_err());
+    }
+    
+    #[test]
+    fn strips_extras_unless_kept() {
+        let mut shared = server("a", "a.dev:443", "");
+        shared.env.insert("LD_PRELOAD".to_string(), "/tmp/evil.so".to_string());
+        shared.extra_args.insert("insecure".to_string(), "true".to_string());
+        let export = ServerExport::new(vec![shared], false);
+        
+        let preview = ImportPreview::new(export.clone(), &[], ImportMode::Merge, false).unwrap();
+        assert_eq!(preview.servers[0].extras, ["-insecure=true", "LD_PRELOAD=/tmp/evil.so"]);
+        assert!(!preview.servers[0].extras_kept);
+        let imported = preview.into_servers();
+        assert!(imported[0].env.is_empty());
+        assert!(imported[0].extra_args.is_empty());
+        
+        let preview = ImportPreview::new(export, &[], ImportMode::Merge, true).unwrap();
+        assert!(preview.servers[0].extras_kept);
+        let imported = preview.into_servers();
+        assert_eq!(imported[0].env.get("LD_PRELOAD").map(String::as_str), Some("/tmp/evil.so"));
+    }
+}
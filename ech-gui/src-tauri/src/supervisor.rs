@@ -0,0 +1,191 @@
+//! Restart supervision for ECH Workers
+//! Decides whether an unexpected worker exit should trigger a restart,
+//! applying exponential backoff and crash-loop detection
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::config::Server;
+
+/// Payload of the `process-restarting` event
+#[derive(Debug, Clone, Serialize)]
+pub struct RestartScheduled {
+    pub server_id: String,
+    pub attempt: u32,
+    pub max_retries: u32,
+    pub delay_ms: u64,
+}
+
+/// Payload of the `process-crash-loop` event
+#[derive(Debug, Clone, Serialize)]
+pub struct CrashLoop {
+    pub server_id: String,
+    pub attempts: u32,
+    pub window_secs: u64,
+}
+
+/// Outcome of an unexpected exit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartDecision {
+    /// Automatic restart is turned off for this server
+    Disabled,
+    /// Restart after `delay`; `attempt` counts restarts within the window
+    Restart { attempt: u32, delay: Duration },
+    /// Too many restarts within the window, stop trying
+    GiveUp { attempts: u32 },
+}
+
+/// Restart history for one supervised server
+pub struct Supervisor {
+    server: Server,
+    restarts: VecDeque<Instant>,
+}
+
+impl Supervisor {
+    pub fn new(server: Server) -> Self {
+        Self {
+            server,
+            restarts: VecDeque::new(),
+        }
+    }
+    
+    /// Server config the worker was started with
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+    
+    /// Record an unexpected exit at `now` and decide what to do next
+    pub fn on_exit(&mut self, now: Instant) -> RestartDecision {
+        let policy = &self.server.restart_policy;
+        if !policy.enabled {
+            return RestartDecision::Disabled;
+        }
+        
+        // Forget restarts that fell out of the window, so a worker that ran
+        // stably for a while starts again from the initial delay
+        let window = Duration::from_secs(policy.window_secs);
+        while let Some(first) = self.restarts.front() {
+            if now.duration_since(*first) > window {
+                self.restarts.pop_front();
+            } else {
+                break;
+            }
+        }
+        
+        let attempts = self.restarts.len() as u32;
+        if attempts >= policy.max_retries {
+            return RestartDecision::GiveUp { attempts };
+        }
+        
+        let factor = 1u64.checked_shl(attempts).unwrap_or(u64::MAX);
+        let delay_ms = policy
+            .initial_delay_ms
+            .saturating_mul(factor)
+            .min(policy.max_delay_ms);
+        
+        self.restarts.push_back(now);
+        RestartDecision::Restart {
+            attempt: attempts + 1,
+            delay: Duration::from_millis(delay_ms),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RestartPolicy;
+    
+    fn supervisor(policy: RestartPolicy) -> Supervisor {
+        Supervisor::new(Server {
+            restart_policy: policy,
+            ..Server::default()
+        })
+    }
+    
+    fn restart_delay(decision: RestartDecision) -> u64 {
+        match decision {
+            RestartDecision::Restart { delay, .. } => delay.as_millis() as u64,
+            other => panic!("expected a restart, got {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn doubles_delay_up_to_the_maximum() {
+        let mut supervisor = supervisor(RestartPolicy {
+            initial_delay_ms: 1000,
+            max_delay_ms: 5000,
+            max_retries: 10,
+            ..RestartPolicy::default()
+        });
+        let start = Instant::now();
+        let delays: Vec<u64> = (0..5)
+            .map(|i| restart_delay(supervisor.on_exit(start + Duration::from_secs(i))))
+            .collect();
+        assert_eq!(delays, [1000, 2000, 4000, 5000, 5000]);
+    }
+    
+    #[test]
+    fn counts_attempts_within_the_window() {
+        let mut supervisor = supervisor(RestartPolicy::default());
+        let start = Instant::now();
+        for attempt in 1..=3 {
+            match supervisor.on_exit(start) {
+                RestartDecision::Restart { attempt: a, .. } => assert_eq!(a, attempt),
+                other => panic!("expected a restart, got {:?}", other),
+            }
+        }
+    }
+    
+    #[test]
+    fn gives_up_after_max_retries_within_the_window() {
+        let mut supervisor = supervisor(RestartPolicy {
+            max_retries: 3,
+            window_secs: 60,
+            ..RestartPolicy::default()
+        });
+        let start = Instant::now();
+        for i in 0..3 {
+            restart_delay(supervisor.on_exit(start + Duration::from_secs(i * 10)));
+        }
+        assert_eq!(
+            supervisor.on_exit(start + Duration::from_secs(30)),
+            RestartDecision::GiveUp { attempts: 3 }
+        );
+    }
+    
+    #[test]
+    fn window_reset_restores_the_initial_delay() {
+        let mut supervisor = supervisor(RestartPolicy {
+            initial_delay_ms: 1000,
+            max_retries: 3,
+            window_secs: 60,
+            ..RestartPolicy::default()
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            restart_delay(supervisor.on_exit(start));
+        }
+        
+        // All earlier restarts fell out of the window
+        let later = start + Duration::from_secs(61);
+        assert_eq!(
+            supervisor.on_exit(later),
+            RestartDecision::Restart {
+                attempt: 1,
+                delay: Duration::from_millis(1000),
+            }
+        );
+        assert_eq!(restart_delay(supervisor.on_exit(later)), 2000);
+    }
+    
+    #[test]
+    fn disabled_policy_never_restarts() {
+        let mut supervisor = supervisor(RestartPolicy {
+            enabled: false,
+            ..RestartPolicy::default()
+        });
+        assert_eq!(supervisor.on_exit(Instant::now()), RestartDecision::Disabled);
+    }
+}
@@ -0,0 +1,432 @@
+//! Command-line arguments of ech-workers
+//! Builds the flags for a server and parses a pasted `ech-workers ...`
+//! command line back into a server, mirroring the flags in the Go `init()`
+
+use std::collections::BTreeMap;
+
+use crate::config::{RoutingMode, Server};
+use crate::worker_binary::TOKEN_ENV;
+
+/// Default of `-l` in the worker
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:30000";
+/// Default of `-dns` in the worker
+pub const DEFAULT_DNS: &str = "dns.alidns.com/dns-query";
+/// Default of `-ech` in the worker
+pub const DEFAULT_ECH: &str = "cloudflare-ech.com";
+/// Default of `-routing` in the worker
+pub const DEFAULT_ROUTING: RoutingMode = RoutingMode::Global;
+
+/// Flags set from the server's own fields, which extra args can't override
+const MANAGED_FLAGS: [&str; 7] = ["l", "f", "ip", "token", "dns", "ech", "routing"];
+
+/// Values of the worker's flags. Empty values are left out of the
+/// arguments, so the worker falls back to its own default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerArgs {
+    /// `-l`
+    pub listen: String,
+    /// `-f`
+    pub server: String,
+    /// `-ip`
+    pub ip: String,
+    /// `-token`
+    pub token: String,
+    /// `-dns`
+    pub dns: String,
+    /// `-ech`
+    pub ech: String,
+    /// `-routing`
+    pub routing: RoutingMode,
+    /// Flags the GUI doesn't manage, by name without the dash
+    pub extra: BTreeMap<String, String>,
+}
+
+impl Default for WorkerArgs {
+    /// The worker's own defaults
+    fn default() -> Self {
+        Self {
+            listen: DEFAULT_LISTEN.to_string(),
+            server: String::new(),
+            ip: String::new(),
+            token: String::new(),
+            dns: DEFAULT_DNS.to_string(),
+            ech: DEFAULT_ECH.to_string(),
+            routing: DEFAULT_ROUTING,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+impl WorkerArgs {
+    pub fn from_server(server: &Server) -> Self {
+        Self {
+            listen: server.listen.clone(),
+            server: server.server.clone(),
+            ip: server.ip.clone(),
+            token: server.token.clone(),
+            dns: server.dns.clone(),
+            ech: server.ech.clone(),
+            routing: server.routing_mode,
+            extra: server.extra_args.clone(),
+        }
+    }
+    
+    /// A new server with these values and default settings otherwise
+    pub fn to_server(&self) -> Server {
+        Server {
+            server: self.server.clone(),
+            listen: self.listen.clone(),
+            token: self.token.clone(),
+            ip: self.ip.clone(),
+            dns: self.dns.clone(),
+            ech: self.ech.clone(),
+            routing_mode: self.routing,
+            extra_args: self.extra.clone(),
+            ..Server::default()
+        }
+    }
+    
+    /// Managed flags and their values in the order of the worker's `init()`
+    fn managed(&self) -> [(&'static str, &str); 7] {
+        [
+            ("l", &self.listen),
+            ("f", &self.server),
+            ("ip", &self.ip),
+            ("token", &self.token),
+            ("dns", &self.dns),
+            ("ech", &self.ech),
+            ("routing", self.routing.as_str()),
+        ]
+    }
+    
+    /// Managed flags as flag/value pairs, followed by the extra flags as
+    /// `-name=value`, which also suits Go boolean flags
+    pub fn to_args(&self) -> Vec<String> {
+        let managed = self
+            .managed()
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .flat_map(|(name, value)| [format!("-{}", name), value.to_string()]);
+        let extra = self
+            .extra
+            .iter()
+            .map(|(name, value)| format!("-{}={}", name, value));
+        managed.chain(extra).collect()
+    }
+    
+    /// Names of the flags `to_args` passes
+    pub fn flag_names(&self) -> Vec<&str> {
+        self.managed()
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, _)| name)
+            .chain(self.extra.keys().map(|name| name.as_str()))
+            .collect()
+    }
+    
+    /// Parse a command line such as `./ech-workers -f host:443 -token x`.
+    /// The program name is optional, and flags follow Go's `flag` syntax:
+    /// `-name value`, `-name=value`, or the same with `--`.
+    pub fn parse(command_line: &str) -> Result<Self, String> {
+        let mut words = split_words(command_line)?.into_iter().peekable();
+        if words.peek().is_some_and(|w| !w.starts_with('-')) {
+            words.next();
+        }
+        
+        let mut args = Self::default();
+        while let Some(word) = words.next() {
+            if word == "--" {
+                break;
+            }
+            let Some(flag) = word.strip_prefix("--").or_else(|| word.strip_prefix('-')) else {
+                return Err(format!("无法识别的参数: {}", word));
+            };
+            let (name, inline) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (flag, None),
+            };
+            
+            let value = match inline {
+                Some(value) => value,
+                None => words
+                    .next()
+                    .ok_or_else(|| format!("参数 -{} 缺少值", name))?,
+            };
+            match name {
+                "l" => args.listen = value,
+                "f" => args.server = value,
+                "ip" => args.ip = value,
+                "token" => args.token = value,
+                "dns" => args.dns = value,
+                "ech" => args.ech = value,
+                "routing" => args.routing = value.parse()?,
+                _ => {
+                    check_flag_name(name)?;
+                    args.extra.insert(name.to_string(), value);
+                }
+            }
+        }
+        
+        if let Some(extra) = words.next() {
+            return Err(format!("无法识别的参数: {}", extra));
+        }
+        Ok(args)
+    }
+}
+
+/// Check a server's extra args and environment. Managed flags and the
+/// token variable always come from the server's own fields.
+pub fn validate_extras(server: &Server) -> Result<(), String> {
+    for name in server.extra_args.keys() {
+        check_flag_name(name)?;
+        if MANAGED_FLAGS.contains(&name.as_str()) {
+            return Err(format!("参数 -{} 由界面管理，请在对应设置项中修改", name));
+        }
+    }
+    for (key, value) in &server.env {
+        if key.is_empty() || key.contains(['=', '\0']) || value.contains('\0') {
+            return Err(format!("无效的环境变量: {}", key));
+        }
+        if key == TOKEN_ENV {
+            return Err(format!("环境变量 {} 由界面管理，请在令牌设置中修改", TOKEN_ENV));
+        }
+    }
+    Ok(())
+}
+
+fn check_flag_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("无效的参数名: -{}", name))
+    }
+}
+
+/// Split a command line into words, honouring single and double quotes,
+/// backslash escapes and line continuations
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = line.chars();
+    
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => match chars.next() {
+                Some(next @ ('"' | '\\')) => word.push(next),
+                Some(next) => {
+                    word.push('\\');
+                    word.push(next);
+                }
+                None => word.push('\\'),
+            },
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, '\\') => match chars.next() {
+                Some('\n') | Some('\r') | None => {}
+                Some(next) => {
+                    word.push(next);
+                    in_word = true;
+                }
+            },
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    
+    if quote.is_some() {
+        return Err("命令行中的引号未闭合".to_string());
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn defaults_match_worker() {
+        let args = WorkerArgs::parse("ech-workers").unwrap();
+        assert_eq!(args.listen, "127.0.0.1:30000");
+        assert_eq!(args.server, "");
+        assert_eq!(args.ip, "");
+        assert_eq!(args.token, "");
+        assert_eq!(args.dns, "dns.alidns.com/dns-query");
+        assert_eq!(args.ech, "cloudflare-ech.com");
+        assert_eq!(args.routing, RoutingMode::Global);
+    }
+    
+    #[test]
+    fn parses_every_flag() {
+        let args = WorkerArgs::parse(
+            "./ech-workers -l 0.0.0.0:1080 -f a.workers.dev:443/ws -ip 1.2.3.4 -token secret \
+             -dns doh.pub/dns-query -ech ech.example.com -routing bypass_cn",
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            WorkerArgs {
+                listen: "0.0.0.0:1080".to_string(),
+                server: "a.workers.dev:443/ws".to_string(),
+                ip: "1.2.3.4".to_string(),
+                token: "secret".to_string(),
+                dns: "doh.pub/dns-query".to_string(),
+                ech: "ech.example.com".to_string(),
+                routing: RoutingMode::BypassCn,
+                extra: BTreeMap::new(),
+            }
+        );
+    }
+    
+    #[test]
+    fn parses_go_flag_syntax() {
+        let args = WorkerArgs::parse("-l=[::1]:1080 --f a.dev:443 --routing=none --").unwrap();
+        assert_eq!(args.listen, "[::1]:1080");
+        assert_eq!(args.server, "a.dev:443");
+        assert_eq!(args.routing, RoutingMode::None);
+    }
+    
+    #[test]
+    fn parses_quotes_and_continuations() {
+        let args = WorkerArgs::parse(
+            "\"C:/Program Files/ech-workers.exe\" -f 'a.dev:443' \\\n -token \"p a\\\"ss\" -ip=\"\"",
+        )
+        .unwrap();
+        assert_eq!(args.server, "a.dev:443");
+        assert_eq!(args.token, "p a\"ss");
+        assert_eq!(args.ip, "");
+    }
+    
+    #[test]
+    fn later_flags_win() {
+        let args = WorkerArgs::parse("-f a.dev:443 -f b.dev:443").unwrap();
+        assert_eq!(args.server, "b.dev:443");
+    }
+    
+    #[test]
+    fn keeps_unknown_flags_as_extra() {
+        let args = WorkerArgs::parse("-f a.dev:443 -debug=true --timeout 5").unwrap();
+        assert_eq!(args.extra.get("debug").map(String::as_str), Some("true"));
+        assert_eq!(args.extra.get("timeout").map(String::as_str), Some("5"));
+        assert_eq!(args.to_server().extra_args, args.extra);
+    }
+    
+    #[test]
+    fn rejects_bad_command_lines() {
+        assert!(WorkerArgs::parse("-x").is_err());
+        assert!(WorkerArgs::parse("-f").is_err());
+        assert!(WorkerArgs::parse("-=1").is_err());
+        assert!(WorkerArgs::parse("-f a.dev:443 extra").is_err());
+        assert!(WorkerArgs::parse("-f a.dev:443 -- -ip 1.2.3.4").is_err());
+        assert!(WorkerArgs::parse("-token 'open").is_err());
+        assert!(WorkerArgs::parse("-routing direct").is_err());
+    }
+    
+    #[test]
+    fn builds_every_flag_in_order() {
+        let args = WorkerArgs {
+            listen: "127.0.0.1:1080".to_string(),
+            server: "a.dev:443".to_string(),
+            ip: "1.2.3.4".to_string(),
+            token: "t".to_string(),
+            dns: DEFAULT_DNS.to_string(),
+            ech: DEFAULT_ECH.to_string(),
+            routing: RoutingMode::None,
+            extra: BTreeMap::new(),
+        };
+        assert_eq!(
+            args.to_args(),
+            [
+                "-l", "127.0.0.1:1080", "-f", "a.dev:443", "-ip", "1.2.3.4", "-token", "t",
+                "-dns", DEFAULT_DNS, "-ech", DEFAULT_ECH, "-routing", "none",
+            ]
+        );
+    }
+    
+    #[test]
+    fn omits_empty_values() {
+        let args = WorkerArgs {
+            listen: "127.0.0.1:1080".to_string(),
+            server: "a.dev:443".to_string(),
+            ip: String::new(),
+            token: String::new(),
+            dns: String::new(),
+            ech: String::new(),
+            routing: RoutingMode::Global,
+            extra: BTreeMap::new(),
+        };
+        assert_eq!(args.to_args(), ["-l", "127.0.0.1:1080", "-f", "a.dev:443", "-routing", "global"]);
+        assert_eq!(args.flag_names(), ["l", "f", "routing"]);
+    }
+    
+    #[test]
+    fn appends_extra_flags() {
+        let mut args = WorkerArgs::parse("-f a.dev:443").unwrap();
+        args.extra.insert("v".to_string(), "true".to_string());
+        let built = args.to_args();
+        assert_eq!(built.last().map(String::as_str), Some("-v=true"));
+        assert!(args.flag_names().contains(&"v"));
+    }
+    
+    #[test]
+    fn validates_extras() {
+        let mut server = Server::default();
+        server.extra_args.insert("newflag".to_string(), "1".to_string());
+        server.env.insert("GODEBUG".to_string(), "http2debug=1".to_string());
+        assert!(validate_extras(&server).is_ok());
+        
+        for flag in MANAGED_FLAGS {
+            let mut server = Server::default();
+            server.extra_args.insert(flag.to_string(), "x".to_string());
+            assert!(validate_extras(&server).is_err(), "-{} must be rejected", flag);
+        }
+        
+        let mut server = Server::default();
+        server.env.insert(TOKEN_ENV.to_string(), "secret".to_string());
+        assert!(validate_extras(&server).is_err());
+        
+        let mut server = Server::default();
+        server.env.insert("A=B".to_string(), "x".to_string());
+        assert!(validate_extras(&server).is_err());
+    }
+    
+    #[test]
+    fn round_trips_through_server() {
+        let server = Server {
+            token: "tok en".to_string(),
+            ..Server::default()
+        };
+        let args = WorkerArgs::from_server(&server);
+        let quoted: Vec<String> = args.to_args().iter().map(|a| format!("'{}'", a)).collect();
+        let parsed = WorkerArgs::parse(&format!("ech-workers {}", quoted.join(" "))).unwrap();
+        assert_eq!(parsed, args);
+        
+        let restored = parsed.to_server();
+        assert_eq!(restored.server, server.server);
+        assert_eq!(restored.listen, server.listen);
+        assert_eq!(restored.token, server.token);
+        assert_eq!(restored.ip, server.ip);
+        assert_eq!(restored.dns, server.dns);
+        assert_eq!(restored.ech, server.ech);
+        assert_eq!(restored.routing_mode, server.routing_mode);
+    }
+}
@@ -0,0 +1,268 @@
+//! Locating and probing the ech-workers executable
+//! The binary is taken from the configured path or searched for, verified
+//! against trusted hashes, and its `-h` output tells which flags it supports
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::IntegritySettings;
+use crate::integrity::IntegrityReport;
+
+/// How long the `-h` probe may run before it is killed
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Environment variable newer workers read the auth token from, keeping it
+/// off the command line. Workers that support it mention it in `-h`.
+pub const TOKEN_ENV: &str = "ECH_WORKERS_TOKEN";
+
+/// Where the executable was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BinarySource {
+    /// The path set in the settings
+    Configured,
+    /// Found next to the app, in the working directory or on `PATH`
+    Discovered,
+}
+
+/// A resolved, verified and probed ech-workers executable
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerBinary {
+    pub integrity: IntegrityReport,
+    /// Flags advertised by `-h`, without the leading dash
+    pub flags: BTreeSet<String>,
+    /// Whether the worker reads the token from `TOKEN_ENV`
+    pub token_env: bool,
+}
+
+impl WorkerBinary {
+    pub fn path(&self) -> &Path {
+        &self.integrity.path
+    }
+    
+    /// Check that every flag in `flags` (without leading dash) is supported
+    pub fn check_flags<'a>(&self, flags: impl IntoIterator<Item = &'a str>) -> Result<(), String> {
+        let unsupported: Vec<String> = flags
+            .into_iter()
+            .filter(|f| !self.flags.contains(*f))
+            .map(|f| format!("-{}", f))
+            .collect();
+        if unsupported.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "当前 ech-workers ({}) 不支持参数 {}，请更新 ech-workers",
+                self.path().display(),
+                unsupported.join(", ")
+            ))
+        }
+    }
+}
+
+/// Resolves the executable, caching the probe while its hash is unchanged
+pub struct WorkerLocator {
+    configured: Mutex<Option<PathBuf>>,
+    integrity: Mutex<IntegritySettings>,
+    probed: Mutex<Option<WorkerBinary>>,
+}
+
+impl WorkerLocator {
+    pub fn new() -> Self {
+        Self {
+            configured: Mutex::new(None),
+            integrity: Mutex::new(IntegritySettings::default()),
+            probed: Mutex::new(None),
+        }
+    }
+    
+    /// Set the explicit executable path; `None` or an empty path searches
+    pub fn configure(&self, path: Option<String>) {
+        *self.configured.lock() = path
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from);
+    }
+    
+    /// Apply new integrity settings
+    pub fn configure_integrity(&self, settings: IntegritySettings) {
+        *self.integrity.lock() = settings;
+    }
+    
+    /// Locate the executable and verify its hash, without running it
+    pub fn inspect(&self) -> Result<IntegrityReport, String> {
+        let configured = self.configured.lock().clone();
+        let (path, source) = match configured {
+            Some(path) => {
+                if !is_executable(&path) {
+                    return Err(format!("指定的 ech-workers 不存在或不可执行: {}", path.display()));
+                }
+                (path, BinarySource::Configured)
+            }
+            None => (
+                find_executable().ok_or_else(|| "找不到 ech-workers 可执行文件".to_string())?,
+                BinarySource::Discovered,
+            ),
+        };
+        
+        let settings = self.integrity.lock().clone();
+        IntegrityReport::new(path, source, &settings)
+    }
+    
+    /// Locate, verify and probe the executable. Fails without running it
+    /// if the integrity mode refuses it.
+    pub fn resolve(&self) -> Result<WorkerBinary, String> {
+        let integrity = self.inspect()?;
+        integrity.check()?;
+        
+        let mut probed = self.probed.lock();
+        if let Some(binary) = probed.as_ref() {
+            if binary.integrity.sha256 == integrity.sha256 {
+                return Ok(WorkerBinary {
+                    integrity,
+                    ..binary.clone()
+                });
+            }
+        }
+        
+        let usage = probe_usage(&integrity.path)?;
+        let flags = parse_usage(&usage);
+        if flags.is_empty() {
+            return Err(format!(
+                "无法识别 ech-workers ({}) 支持的参数，请确认文件是否正确",
+                integrity.path.display()
+            ));
+        }
+        
+        let binary = WorkerBinary {
+            flags,
+            token_env: usage.contains(TOKEN_ENV),
+            integrity,
+        };
+        *probed = Some(binary.clone());
+        Ok(binary)
+    }
+}
+
+/// Run the executable with `-h` and return its usage text
+fn probe_usage(path: &Path) -> Result<String, String> {
+    let mut cmd = Command::new(path);
+    cmd.arg("-h")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+    }
+    
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| format!("无法运行 ech-workers ({}): {}", path.display(), e))?;
+    
+    // The usage text is small, so it fits in the pipe buffers until exit
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("ech-workers ({}) 未响应 -h", path.display()));
+            }
+        }
+    }
+    
+    // Go's flag package prints usage to stderr
+    let mut usage = String::new();
+    if let Some(mut stderr) = child.stderr.take() {
+        let _ = stderr.read_to_string(&mut usage);
+    }
+    if let Some(mut stdout) = child.stdout.take() {
+        let _ = stdout.read_to_string(&mut usage);
+    }
+    Ok(usage)
+}
+
+/// Extract flag names from Go `flag` usage output, where each flag starts
+/// an indented line such as `  -routing string`
+fn parse_usage(usage: &str) -> BTreeSet<String> {
+    usage
+        .lines()
+        .filter(|line| line.starts_with(' '))
+        .filter_map(|line| line.trim_start().strip_prefix('-'))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .filter(|name| name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(windows)]
+    {
+        path.is_file()
+    }
+}
+
+/// Search for the ech-workers executable
+fn find_executable() -> Option<PathBuf> {
+    let exe_name = if cfg!(target_os = "windows") {
+        "ech-workers.exe"
+    } else {
+        "ech-workers"
+    };
+    
+    // Get the directory where the app is located
+    let app_dir = std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|p| p.to_path_buf()));
+    
+    // Possible locations to search
+    let mut search_paths = Vec::new();
+    
+    // 1. App bundle directory (for packaged apps)
+    if let Some(dir) = &app_dir {
+        search_paths.push(dir.join(exe_name));
+        // On macOS, check Resources folder
+        if cfg!(target_os = "macos") {
+            search_paths.push(dir.join("../Resources").join(exe_name));
+        }
+    }
+    
+    // 2. Parent directory (for development - ech-gui is inside ech-wk)
+    if let Some(dir) = &app_dir {
+        search_paths.push(dir.join("../../..").join(exe_name));
+        search_paths.push(dir.join("../../../..").join(exe_name));
+    }
+    
+    // 3. Current working directory
+    search_paths.push(PathBuf::from(exe_name));
+    
+    // 4. Parent of current directory
+    search_paths.push(PathBuf::from("..").join(exe_name));
+    
+    for path in search_paths {
+        if let Ok(canonical) = path.canonicalize() {
+            if is_executable(&canonical) {
+                return Some(canonical);
+            }
+        }
+    }
+    
+    // 5. Try PATH
+    which::which(exe_name).ok()
+}
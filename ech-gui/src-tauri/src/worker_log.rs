@@ -0,0 +1,358 @@
+//! Structured parsing of ECH Workers log output
+//! Turns the worker's fixed-format log lines into typed events
+
+use serde::Serialize;
+
+/// How the worker routed a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Route {
+    /// Tunnelled through the ECH WebSocket
+    Proxy,
+    /// Connected directly, bypassing the proxy
+    Direct,
+}
+
+/// A typed event recognised in a worker log line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WorkerEvent {
+    /// `[启动] 正在获取 ECH 配置...`
+    FetchingEch,
+    /// `[启动] 获取 ECH 配置失败: <error>`
+    EchFetchFailed { error: String },
+    /// `[ECH] 配置已加载，长度: <n> 字节`
+    EchLoaded { bytes: usize },
+    /// `[ECH] 刷新配置...`
+    EchRefreshing,
+    /// `[ECH] 连接失败，尝试刷新配置 (<attempt>/<max>)`
+    EchRetry { attempt: u32, max_attempts: u32 },
+    /// `[启动] 已加载 <n> 个中国IPv4段, <m> 个中国IPv6段`
+    ChinaIpListLoaded { ipv4_ranges: usize, ipv6_ranges: usize },
+    /// `[代理] 服务器启动: <addr> (支持 SOCKS5 和 HTTP)`
+    Listening { addr: String },
+    /// `[代理] 监听失败: <error>`
+    ListenFailed { error: String },
+    /// `[SOCKS5] <client> -> <target>` or `[HTTP-<method>] <client> -> <url>`
+    Request {
+        protocol: String,
+        client: String,
+        target: String,
+    },
+    /// `[分流] <client> -> <target> (直连，绕过代理|通过代理)`
+    RouteDecision {
+        client: String,
+        target: String,
+        route: Route,
+    },
+    /// `[代理] <client> 已连接: <target>`
+    Connected { client: String, target: String },
+    /// `[代理] <client> 已断开: <target>`
+    Disconnected { client: String, target: String },
+    /// `[分流] <client> 直连已断开: <target>`
+    DirectClosed { client: String, target: String },
+    /// `[<protocol>] <client> 代理失败: <error>`
+    ProxyFailed {
+        protocol: String,
+        client: String,
+        error: String,
+    },
+    /// `[警告] <message>`
+    Warning { message: String },
+}
+
+/// Payload of the `worker-event` event; `seq` links it to the raw log entry
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerEventPayload {
+    pub server_id: String,
+    pub seq: u64,
+    pub timestamp: u64,
+    pub event: WorkerEvent,
+}
+
+/// Parse a worker log line, with or without Go's date/time prefix.
+/// Returns `None` for lines that carry no structured information.
+pub fn parse_line(line: &str) -> Option<WorkerEvent> {
+    let start = line.find('[')?;
+    let (tag, msg) = line[start + 1..].split_once("] ")?;
+    let msg = msg.trim_end();
+    
+    match tag {
+        "启动" => parse_startup(msg),
+        "ECH" => parse_ech(msg),
+        "代理" => parse_proxy(msg),
+        "分流" => parse_routing(msg),
+        "警告" => Some(WorkerEvent::Warning {
+            message: msg.to_string(),
+        }),
+        "SOCKS5" => parse_request(tag, msg),
+        _ if tag.starts_with("HTTP-") => parse_request(tag, msg),
+        _ => None,
+    }
+}
+
+fn parse_startup(msg: &str) -> Option<WorkerEvent> {
+    if msg.starts_with("正在获取 ECH 配置") {
+        return Some(WorkerEvent::FetchingEch);
+    }
+    if let Some(error) = msg.strip_prefix("获取 ECH 配置失败: ") {
+        return Some(WorkerEvent::EchFetchFailed {
+            error: error.to_string(),
+        });
+    }
+    
+    // 已加载 N 个中国IPv4段, M 个中国IPv6段
+    let rest = msg.strip_prefix("已加载 ")?;
+    let (ipv4, rest) = rest.split_once(" 个中国IPv4段, ")?;
+    let ipv6 = rest.strip_suffix(" 个中国IPv6段")?;
+    Some(WorkerEvent::ChinaIpListLoaded {
+        ipv4_ranges: ipv4.parse().ok()?,
+        ipv6_ranges: ipv6.parse().ok()?,
+    })
+}
+
+fn parse_ech(msg: &str) -> Option<WorkerEvent> {
+    if msg.starts_with("刷新配置") {
+        return Some(WorkerEvent::EchRefreshing);
+    }
+    if let Some(len) = msg.strip_prefix("配置已加载，长度: ") {
+        let bytes = len.strip_suffix(" 字节")?.parse().ok()?;
+        return Some(WorkerEvent::EchLoaded { bytes });
+    }
+    
+    // 连接失败，尝试刷新配置 (attempt/max)
+    let counts = msg
+        .strip_prefix("连接失败，尝试刷新配置 (")?
+        .strip_suffix(')')?;
+    let (attempt, max) = counts.split_once('/')?;
+    Some(WorkerEvent::EchRetry {
+        attempt: attempt.parse().ok()?,
+        max_attempts: max.parse().ok()?,
+    })
+}
+
+fn parse_proxy(msg: &str) -> Option<WorkerEvent> {
+    if let Some(rest) = msg.strip_prefix("服务器启动: ") {
+        let addr = rest.split(' ').next()?;
+        return Some(WorkerEvent::Listening {
+            addr: addr.to_string(),
+        });
+    }
+    if let Some(error) = msg.strip_prefix("监听失败: ") {
+        return Some(WorkerEvent::ListenFailed {
+            error: error.to_string(),
+        });
+    }
+    
+    let (client, rest) = msg.split_once(' ')?;
+    if let Some(target) = rest.strip_prefix("已连接: ") {
+        return Some(WorkerEvent::Connected {
+            client: client.to_string(),
+            target: target.to_string(),
+        });
+    }
+    let target = rest.strip_prefix("已断开: ")?;
+    Some(WorkerEvent::Disconnected {
+        client: client.to_string(),
+        target: target.to_string(),
+    })
+}
+
+fn parse_routing(msg: &str) -> Option<WorkerEvent> {
+    if let Some((client, rest)) = msg.split_once(" -> ") {
+        let (target, reason) = rest.rsplit_once(" (")?;
+        let route = match reason.strip_suffix(')')? {
+            "直连，绕过代理" => Route::Direct,
+            "通过代理" => Route::Proxy,
+            _ => return None,
+        };
+        return Some(WorkerEvent::RouteDecision {
+            client: client.to_string(),
+            target: target.to_string(),
+            route,
+        });
+    }
+    
+    let (client, rest) = msg.split_once(' ')?;
+    let target = rest.strip_prefix("直连已断开: ")?;
+    Some(WorkerEvent::DirectClosed {
+        client: client.to_string(),
+        target: target.to_string(),
+    })
+}
+
+fn parse_request(protocol: &str, msg: &str) -> Option<WorkerEvent> {
+    if let Some((client, target)) = msg.split_once(" -> ") {
+        return Some(WorkerEvent::Request {
+            protocol: protocol.to_string(),
+            client: client.to_string(),
+            target: target.to_string(),
+        });
+    }
+    
+    let (client, rest) = msg.split_once(' ')?;
+    let error = rest.strip_prefix("代理失败: ")?;
+    Some(WorkerEvent::ProxyFailed {
+        protocol: protocol.to_string(),
+        client: client.to_string(),
+        error: error.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Parse a line as the worker prints it, with Go's log prefix
+    fn parse(message: &str) -> Option<WorkerEvent> {
+        parse_line(&format!("2025/01/02 15:04:05 {}\n", message))
+    }
+    
+    #[test]
+    fn parses_startup_lines() {
+        assert_eq!(parse("[启动] 正在获取 ECH 配置..."), Some(WorkerEvent::FetchingEch));
+        assert_eq!(
+            parse("[启动] 获取 ECH 配置失败: DNS 查询失败: timeout"),
+            Some(WorkerEvent::EchFetchFailed {
+                error: "DNS 查询失败: timeout".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("[启动] 已加载 8123 个中国IPv4段, 1502 个中国IPv6段"),
+            Some(WorkerEvent::ChinaIpListLoaded {
+                ipv4_ranges: 8123,
+                ipv6_ranges: 1502,
+            })
+        );
+        assert_eq!(parse("[启动] 分流模式: 全局代理"), None);
+    }
+    
+    #[test]
+    fn parses_ech_lines() {
+        assert_eq!(
+            parse("[ECH] 配置已加载，长度: 71 字节"),
+            Some(WorkerEvent::EchLoaded { bytes: 71 })
+        );
+        assert_eq!(parse("[ECH] 刷新配置..."), Some(WorkerEvent::EchRefreshing));
+        assert_eq!(
+            parse("[ECH] 连接失败，尝试刷新配置 (2/3)"),
+            Some(WorkerEvent::EchRetry {
+                attempt: 2,
+                max_attempts: 3,
+            })
+        );
+    }
+    
+    #[test]
+    fn parses_proxy_lines() {
+        assert_eq!(
+            parse("[代理] 服务器启动: 127.0.0.1:30000 (支持 SOCKS5 和 HTTP)"),
+            Some(WorkerEvent::Listening {
+                addr: "127.0.0.1:30000".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("[代理] 监听失败: listen tcp 127.0.0.1:30000: bind: address already in use"),
+            Some(WorkerEvent::ListenFailed {
+                error: "listen tcp 127.0.0.1:30000: bind: address already in use".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("[代理] 127.0.0.1:52814 已连接: www.google.com:443"),
+            Some(WorkerEvent::Connected {
+                client: "127.0.0.1:52814".to_string(),
+                target: "www.google.com:443".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("[代理] 127.0.0.1:52814 已断开: www.google.com:443"),
+            Some(WorkerEvent::Disconnected {
+                client: "127.0.0.1:52814".to_string(),
+                target: "www.google.com:443".to_string(),
+            })
+        );
+        assert_eq!(parse("[代理] 后端服务器: example.com:443"), None);
+    }
+    
+    #[test]
+    fn parses_routing_lines() {
+        assert_eq!(
+            parse("[分流] 127.0.0.1:52815 -> www.baidu.com:443 (直连，绕过代理)"),
+            Some(WorkerEvent::RouteDecision {
+                client: "127.0.0.1:52815".to_string(),
+                target: "www.baidu.com:443".to_string(),
+                route: Route::Direct,
+            })
+        );
+        assert_eq!(
+            parse("[分流] 127.0.0.1:52816 -> [2001:db8::1]:443 (通过代理)"),
+            Some(WorkerEvent::RouteDecision {
+                client: "127.0.0.1:52816".to_string(),
+                target: "[2001:db8::1]:443".to_string(),
+                route: Route::Proxy,
+            })
+        );
+        assert_eq!(
+            parse("[分流] 127.0.0.1:52815 直连已断开: www.baidu.com:443"),
+            Some(WorkerEvent::DirectClosed {
+                client: "127.0.0.1:52815".to_string(),
+                target: "www.baidu.com:443".to_string(),
+            })
+        );
+    }
+    
+    #[test]
+    fn parses_request_lines() {
+        assert_eq!(
+            parse("[SOCKS5] 127.0.0.1:52817 -> github.com:443"),
+            Some(WorkerEvent::Request {
+                protocol: "SOCKS5".to_string(),
+                client: "127.0.0.1:52817".to_string(),
+                target: "github.com:443".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("[HTTP-CONNECT] 127.0.0.1:52818 -> github.com:443"),
+            Some(WorkerEvent::Request {
+                protocol: "HTTP-CONNECT".to_string(),
+                client: "127.0.0.1:52818".to_string(),
+                target: "github.com:443".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("[HTTP-GET] 127.0.0.1:52819 -> http://example.com/index.html"),
+            Some(WorkerEvent::Request {
+                protocol: "HTTP-GET".to_string(),
+                client: "127.0.0.1:52819".to_string(),
+                target: "http://example.com/index.html".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("[HTTP-POST] 127.0.0.1:52819 代理失败: websocket: bad handshake"),
+            Some(WorkerEvent::ProxyFailed {
+                protocol: "HTTP-POST".to_string(),
+                client: "127.0.0.1:52819".to_string(),
+                error: "websocket: bad handshake".to_string(),
+            })
+        );
+        assert_eq!(parse("[SOCKS5] 127.0.0.1:52817 版本错误: 0x04"), None);
+    }
+    
+    #[test]
+    fn parses_warnings_and_lines_without_date() {
+        assert_eq!(
+            parse_line("[警告] 未加载到任何中国IP列表，将使用默认规则"),
+            Some(WorkerEvent::Warning {
+                message: "未加载到任何中国IP列表，将使用默认规则".to_string(),
+            })
+        );
+    }
+    
+    #[test]
+    fn ignores_unrecognised_lines() {
+        assert_eq!(parse("[UDP] 127.0.0.1:52820 UDP ASSOCIATE 连接关闭"), None);
+        assert_eq!(parse("[下载] 正在下载 IP 列表: https://example.com/chn_ip.txt"), None);
+        assert_eq!(parse_line("panic: runtime error: index out of range"), None);
+        assert_eq!(parse_line(""), None);
+    }
+}
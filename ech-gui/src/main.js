@@ -29,6 +29,7 @@ const ui = {
     ech: document.getElementById('ech'),
     routing: document.getElementsByName('routing')
   },
+  routingOptions: document.getElementById('routing-options'),
   
   btnStart: document.getElementById('btn-start'),
   btnStop: document.getElementById('btn-stop'),
@@ -47,7 +48,19 @@ const ui = {
   modalInput: document.getElementById('modal-input'),
   modalConfirm: document.getElementById('modal-confirm'),
   modalCancel: document.getElementById('modal-cancel'),
-  modalClose: document.getElementById('modal-close')
+  modalClose: document.getElementById('modal-close'),
+  
+  // Import / Export
+  btnExport: document.getElementById('btn-export'),
+  btnImport: document.getElementById('btn-import'),
+  importFile: document.getElementById('import-file'),
+  transferOverlay: document.getElementById('transfer-overlay'),
+  transferTitle: document.getElementById('transfer-title'),
+  transferOptions: document.getElementById('transfer-options'),
+  transferList: document.getElementById('transfer-list'),
+  transferConfirm: document.getElementById('transfer-confirm'),
+  transferCancel: document.getElementById('transfer-cancel'),
+  transferClose: document.getElementById('transfer-close')
 };
 
 /**
@@ -61,8 +74,11 @@ window.addEventListener('DOMContentLoaded', async () => {
     const platform = await invoke('get_app_version'); // Just a check
     
     // Load data
+    await renderRoutingModes();
     await refreshServers();
     await checkProcessStatus();
+    await restoreLogs();
+    await reportConfigRecovery();
     
     // Setup listeners
     setupEventListeners();
@@ -80,20 +96,33 @@ window.addEventListener('DOMContentLoaded', async () => {
 function setupEventListeners() {
   // Server Selection
   ui.serverSelect.addEventListener('change', async (e) => {
-    if (state.isRunning) {
-      alert("请先停止当前连接后再切换服务器");
-      // Revert selection
+    const newId = e.target.value;
+    const oldId = state.currentServerId;
+    
+    // Hand over from a running server without a gap in service
+    if (state.isRunning && !(await invoke('is_process_running', { id: newId }))) {
+      ui.serverSelect.disabled = true;
+      try {
+        // Success is logged by the server-switched listener
+        await invoke('switch_server', { id: newId });
+      } catch (err) {
+        appendLog(`[错误] 切换服务器失败: ${err}`);
+      }
+      ui.serverSelect.disabled = false;
+      state.currentServerId = await invoke('get_current_server_id');
       ui.serverSelect.value = state.currentServerId;
+      if (state.currentServerId !== oldId) await loadCurrentServer();
+      updateProcessState(await invoke('is_process_running'));
+      updateProxyState(await invoke('get_proxy_status'));
       return;
     }
     
-    // Auto-save current before switching?
-    // For now, let's just switch and load
-    const newId = e.target.value;
+    // Other servers may keep running; the controls follow the selection
     try {
       await invoke('set_current_server', { id: newId });
       state.currentServerId = newId;
       await loadCurrentServer();
+      updateProcessState(await invoke('is_process_running'));
     } catch (err) {
       appendLog(`[错误] 切换服务器失败: ${err}`);
     }
@@ -140,11 +169,24 @@ function setupEventListeners() {
       appendLog(`[错误] ${err}`);
     }
   });
+  
+  ui.btnExport.addEventListener('click', openExport);
+  ui.btnImport.addEventListener('click', () => ui.importFile.click());
+  ui.importFile.addEventListener('change', async () => {
+    const file = ui.importFile.files[0];
+    ui.importFile.value = '';
+    if (file) await openImport(await file.text());
+  });
 
   // UI Toggles
   ui.toggleAdvanced.addEventListener('click', () => {
     ui.advancedPanel.classList.toggle('collapsed');
   });
+  
+  // Inline validation
+  for (const field of VALIDATED_FIELDS) {
+    ui.inputs[field].addEventListener('change', validateForm);
+  }
 }
 
 /**
@@ -152,17 +194,49 @@ function setupEventListeners() {
  */
 async function setupTauriListeners() {
   await listen('log-output', (event) => {
-    appendLog(event.payload);
+    appendLog(event.payload.line);
   });
   
-  await listen('process-started', () => {
-    updateProcessState(true);
-    appendLog('[系统] 进程已启动');
+  await listen('process-started', (event) => {
+    if (event.payload.server_id === state.currentServerId) updateProcessState(true);
+    appendLog(`[系统] ${serverName(event.payload.server_id)} 进程已启动`);
   });
   
-  await listen('process-stopped', () => {
-    updateProcessState(false);
-    appendLog('[系统] 进程已停止');
+  await listen('process-stopped', (event) => {
+    const { server_id, outcome } = event.payload;
+    if (server_id === state.currentServerId) updateProcessState(false);
+    const suffix = outcome === 'forced' ? ' (已强制结束)' : '';
+    appendLog(`[系统] ${serverName(server_id)} 进程已停止${suffix}`);
+  });
+
+  await listen('process-exited', (event) => {
+    const { server_id, code, signal } = event.payload;
+    if (server_id === state.currentServerId) updateProcessState(false);
+    const reason = signal != null ? `信号 ${signal}` : `退出码 ${code}`;
+    appendLog(`[错误] 进程意外退出 (${reason})`);
+  });
+
+  await listen('process-state', (event) => {
+    const { server_id, state: workerState } = event.payload;
+    if (workerState === 'fetching_ech') {
+      appendLog(`[系统] ${serverName(server_id)} 正在获取 ECH 配置...`);
+    }
+  });
+
+  await listen('server-switched', (event) => {
+    const { from, to, mode } = event.payload;
+    const how = mode === 'handoff' ? '同端口交接' : mode === 'overlap' ? '无缝切换' : '启动';
+    appendLog(`[系统] 已从 ${serverName(from)} 切换到 ${serverName(to)} (${how})`);
+  });
+
+  await listen('process-restarting', (event) => {
+    const { attempt, max_retries, delay_ms } = event.payload;
+    appendLog(`[系统] ${delay_ms / 1000} 秒后自动重启 (${attempt}/${max_retries})`);
+  });
+
+  await listen('process-crash-loop', (event) => {
+    const { attempts, window_secs } = event.payload;
+    appendLog(`[错误] 进程在 ${window_secs} 秒内崩溃 ${attempts} 次，已停止自动重启`);
   });
 }
 
@@ -170,6 +244,34 @@ async function setupTauriListeners() {
  * Core Functions
  */
 
+function serverName(id) {
+  const server = state.servers.find(s => s.id === id);
+  return server ? server.name : id;
+}
+
+async function reportConfigRecovery() {
+  const recovery = await invoke('get_config_recovery');
+  if (!recovery) return;
+  if (!recovery.error) {
+    for (const notice of recovery.repaired) appendLog(`[警告] ${notice}`);
+    return;
+  }
+  appendLog(`[错误] 配置文件无法读取: ${recovery.error}`);
+  if (recovery.read_only) {
+    appendLog('[系统] 原配置文件未作改动，当前使用默认配置，修改不会被保存');
+    return;
+  }
+  if (recovery.quarantined) appendLog(`[系统] 原文件已保存为 ${recovery.quarantined}`);
+  appendLog(recovery.restored_from
+    ? `[系统] 已从备份恢复配置: ${recovery.restored_from}`
+    : '[系统] 没有可用的备份，已重置为默认配置');
+}
+
+async function restoreLogs() {
+  const page = await invoke('get_logs', { query: { limit: 500 } });
+  page.entries.forEach(entry => appendLog(entry.line));
+}
+
 async function refreshServers() {
   const servers = await invoke('get_servers');
   const currentId = await invoke('get_current_server_id');
@@ -203,15 +305,76 @@ async function loadCurrentServer() {
   ui.inputs.ech.value = server.ech || '';
   
   // Radio buttons
-  const routing = server.routing_mode || 'bypass_cn';
   for (const radio of ui.inputs.routing) {
-    if (radio.value === routing) {
+    if (radio.value === server.routing_mode) {
       radio.checked = true;
       // Update visual style
       updateRadioVisual(radio);
     }
-    // Add change listener for visuals
+  }
+  
+  await validateForm();
+}
+
+// Fields with inline errors, named as in the server config
+const VALIDATED_FIELDS = ['server', 'listen', 'ip', 'dns', 'ech'];
+
+async function validateForm() {
+  const server = await getFormData();
+  if (!server) return;
+  
+  const errors = await invoke('validate_server', { server });
+  for (const field of VALIDATED_FIELDS) {
+    const input = ui.inputs[field];
+    const error = errors.find(e => e.field === field);
+    const group = input.closest('.form-group');
+    let hint = group.querySelector('.field-error');
+    if (!hint) {
+      hint = document.createElement('div');
+      hint.className = 'field-error';
+      group.appendChild(hint);
+    }
+    input.classList.toggle('invalid', !!error);
+    hint.textContent = error ? error.message : '';
+  }
+  
+  // Routing modes are radio cards, so their error goes below the group
+  const routingError = errors.find(e => e.field === 'routing_mode');
+  const panel = ui.routingOptions.parentElement;
+  let routingHint = panel.querySelector('.field-error');
+  if (!routingHint) {
+    routingHint = document.createElement('div');
+    routingHint.className = 'field-error';
+    panel.appendChild(routingHint);
+  }
+  routingHint.textContent = routingError ? routingError.message : '';
+}
+
+async function renderRoutingModes() {
+  const modes = await invoke('get_routing_modes');
+  ui.routingOptions.innerHTML = '';
+  for (const { mode, label, description } of modes) {
+    const card = document.createElement('label');
+    card.className = 'radio-card';
+    
+    const radio = document.createElement('input');
+    radio.type = 'radio';
+    radio.name = 'routing';
+    radio.value = mode;
     radio.addEventListener('change', () => updateRadioVisual(radio));
+    
+    const text = document.createElement('span');
+    text.className = 'radio-label';
+    const title = document.createElement('span');
+    title.className = 'radio-title';
+    title.textContent = label;
+    const desc = document.createElement('span');
+    desc.className = 'radio-desc';
+    desc.textContent = description;
+    text.append(title, desc);
+    
+    card.append(radio, text);
+    ui.routingOptions.appendChild(card);
   }
 }
 
@@ -224,7 +387,7 @@ async function getFormData() {
   const server = await invoke('get_current_server');
   if (!server) return null;
   
-  let routingMode = 'bypass_cn';
+  let routingMode = server.routing_mode;
   for (const radio of ui.inputs.routing) {
     if (radio.checked) routingMode = radio.value;
   }
@@ -248,6 +411,7 @@ async function saveConfig() {
   try {
     await invoke('update_server', { server: updatedServer });
     appendLog('[系统] 配置已保存');
+    await validateForm();
     
     // Animation feedback
     ui.btnSave.textContent = '已保存 ✓';
@@ -268,6 +432,7 @@ async function startProcess() {
     appendLog(`[系统] ${msg}`);
     updateProcessState(true);
   } catch (err) {
+    updateProcessState(false);
     appendLog(`[错误] 启动失败: ${err}`);
   }
 }
@@ -290,22 +455,25 @@ async function toggleProxy() {
   const newState = !state.isProxyEnabled;
   try {
     const msg = await invoke('set_system_proxy', { enabled: newState });
-    state.isProxyEnabled = newState;
     appendLog(`[系统] ${msg}`);
-    
-    if (newState) {
-      ui.btnProxy.classList.add('active');
-      ui.btnProxy.innerHTML = '<span class="btn-icon">⚡</span><span class="btn-text">关闭系统代理</span>';
-      ui.btnProxy.querySelector('.btn-icon').style.color = '#fff';
-    } else {
-      ui.btnProxy.classList.remove('active');
-      ui.btnProxy.innerHTML = '<span class="btn-icon">⚡</span><span class="btn-text">设置系统代理</span>';
-    }
+    updateProxyState(newState);
   } catch (err) {
     appendLog(`[错误] 代理设置失败: ${err}`);
   }
 }
 
+function updateProxyState(enabled) {
+  state.isProxyEnabled = enabled;
+  if (enabled) {
+    ui.btnProxy.classList.add('active');
+    ui.btnProxy.innerHTML = '<span class="btn-icon">⚡</span><span class="btn-text">关闭系统代理</span>';
+    ui.btnProxy.querySelector('.btn-icon').style.color = '#fff';
+  } else {
+    ui.btnProxy.classList.remove('active');
+    ui.btnProxy.innerHTML = '<span class="btn-icon">⚡</span><span class="btn-text">设置系统代理</span>';
+  }
+}
+
 async function checkProcessStatus() {
   const isRunning = await invoke('is_process_running');
   updateProcessState(isRunning);
@@ -331,7 +499,6 @@ function updateProcessState(isRunning) {
       input.disabled = isRunning;
     }
   });
-  ui.serverSelect.disabled = isRunning;
 }
 
 function appendLog(text) {
@@ -386,3 +553,139 @@ ui.modalConfirm.addEventListener('click', () => {
 ui.modalInput.addEventListener('keypress', (e) => {
   if (e.key === 'Enter') ui.modalConfirm.click();
 });
+
+/**
+ * Import / Export
+ */
+let transferCallback = null;
+
+function showTransferModal(title, callback) {
+  ui.transferTitle.textContent = title;
+  transferCallback = callback;
+  ui.transferOverlay.classList.add('active');
+}
+
+function closeTransferModal() {
+  ui.transferOverlay.classList.remove('active');
+  transferCallback = null;
+  ui.transferOptions.innerHTML = '';
+  ui.transferList.innerHTML = '';
+}
+
+// A labelled checkbox or radio button
+function choice(type, text, checked = false, name = '') {
+  const label = document.createElement('label');
+  const input = document.createElement('input');
+  input.type = type;
+  input.name = name;
+  input.checked = checked;
+  label.append(input, ` ${text}`);
+  return { label, input };
+}
+
+// A row of the server list, with notes below the name
+function transferRow(control, title, notes = []) {
+  const row = document.createElement('label');
+  row.className = 'transfer-item';
+  const text = document.createElement('span');
+  text.textContent = title;
+  for (const { text: note, warning } of notes) {
+    const line = document.createElement('span');
+    line.className = warning ? 'transfer-note warning' : 'transfer-note';
+    line.textContent = note;
+    text.appendChild(line);
+  }
+  if (control) row.appendChild(control);
+  row.appendChild(text);
+  return row;
+}
+
+function openExport() {
+  const includeToken = choice('checkbox', '包含 Token');
+  ui.transferOptions.appendChild(includeToken.label);
+  
+  const selected = state.servers.map(server => {
+    const box = document.createElement('input');
+    box.type = 'checkbox';
+    box.checked = true;
+    ui.transferList.appendChild(transferRow(box, server.name, [{ text: server.server }]));
+    return { id: server.id, box };
+  });
+  
+  showTransferModal('导出服务器', async () => {
+    const ids = selected.filter(s => s.box.checked).map(s => s.id);
+    const path = await invoke('export_servers', { ids, includeToken: includeToken.input.checked });
+    appendLog(`[系统] 已导出 ${ids.length} 个服务器到 ${path}`);
+  });
+}
+
+async function openImport(content) {
+  let mode = 'merge';
+  let keepExtras = false;
+  
+  const render = async () => {
+    const preview = await invoke('preview_import_servers', { content, mode, keepExtras });
+    ui.transferList.innerHTML = '';
+    for (const candidate of preview.servers) {
+      const notes = [{ text: candidate.server.server }];
+      if (candidate.duplicate_of) notes.push({ text: `与 ${candidate.duplicate_of} 重复，将跳过` });
+      if (candidate.id_changed) notes.push({ text: 'ID 已存在，将使用新 ID' });
+      if (!preview.includes_token) notes.push({ text: '不含 Token', warning: true });
+      for (const error of candidate.errors) notes.push({ text: error.message, warning: true });
+      for (const extra of candidate.extras) {
+        notes.push({ text: `${extra} (${candidate.extras_kept ? '将导入' : '已忽略'})`, warning: true });
+      }
+      
+      const row = transferRow(null, candidate.server.name, notes);
+      if (candidate.duplicate_of) row.classList.add('skipped');
+      ui.transferList.appendChild(row);
+    }
+    return preview;
+  };
+  
+  let preview;
+  try {
+    preview = await render();
+  } catch (err) {
+    appendLog(`[错误] 导入失败: ${err}`);
+    return;
+  }
+  
+  for (const [value, text] of [['merge', '合并到现有服务器'], ['replace', '替换全部服务器']]) {
+    const option = choice('radio', text, value === mode, 'import-mode');
+    option.input.addEventListener('change', async () => {
+      mode = value;
+      await render().catch(err => appendLog(`[错误] ${err}`));
+    });
+    ui.transferOptions.appendChild(option.label);
+  }
+  
+  // Extra flags and environment variables can run arbitrary code
+  if (preview.servers.some(c => c.extras.length > 0)) {
+    const option = choice('checkbox', '导入额外参数和环境变量 (仅限可信来源)');
+    option.input.addEventListener('change', async () => {
+      keepExtras = option.input.checked;
+      await render().catch(err => appendLog(`[错误] ${err}`));
+    });
+    ui.transferOptions.appendChild(option.label);
+  }
+  
+  showTransferModal('导入服务器', async () => {
+    const count = await invoke('import_servers', { content, mode, keepExtras });
+    await refreshServers();
+    appendLog(`[系统] 已导入 ${count} 个服务器`);
+  });
+}
+
+ui.transferClose.addEventListener('click', closeTransferModal);
+ui.transferCancel.addEventListener('click', closeTransferModal);
+
+ui.transferConfirm.addEventListener('click', async () => {
+  if (!transferCallback) return;
+  try {
+    await transferCallback();
+    closeTransferModal();
+  } catch (err) {
+    appendLog(`[错误] ${ui.transferTitle.textContent}失败: ${err}`);
+  }
+});
\ No newline at end of file
@@ -61,11 +61,14 @@ type ipRangeV6 struct {
 	end   [16]byte
 }
 
+// 令牌也可通过环境变量传入，避免出现在进程命令行中
+const tokenEnv = "ECH_WORKERS_TOKEN"
+
 func init() {
 	flag.StringVar(&listenAddr, "l", "127.0.0.1:30000", "代理监听地址 (支持 SOCKS5 和 HTTP)")
 	flag.StringVar(&serverAddr, "f", "", "服务端地址 (格式: x.x.workers.dev:443)")
 	flag.StringVar(&serverIP, "ip", "", "指定服务端 IP（绕过 DNS 解析）")
-	flag.StringVar(&token, "token", "", "身份验证令牌")
+	flag.StringVar(&token, "token", "", "身份验证令牌 (也可通过环境变量 "+tokenEnv+" 传入)")
 	flag.StringVar(&dnsServer, "dns", "dns.alidns.com/dns-query", "ECH 查询 DoH 服务器")
 	flag.StringVar(&echDomain, "ech", "cloudflare-ech.com", "ECH 查询域名")
 	flag.StringVar(&routingMode, "routing", "global", "分流模式: global(全局代理), bypass_cn(跳过中国大陆), none(不改变代理)")
@@ -74,6 +77,11 @@ func init() {
 func main() {
 	flag.Parse()
 
+	if token == "" {
+		token = os.Getenv(tokenEnv)
+	}
+	os.Unsetenv(tokenEnv)
+
 	if serverAddr == "" {
 		log.Fatal("必须指定服务端地址 -f\n\n示例:\n  ./client -l 127.0.0.1:1080 -f your-worker.workers.dev:443 -token your-token")
 	}